@@ -0,0 +1,146 @@
+extern crate proc_macro;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+
+use syn::{Body, DeriveInput, Field, Ident, MetaItem, NestedMetaItem, VariantData};
+
+#[proc_macro_derive(StateRDT)]
+pub fn derive_state_rdt(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let gen = impl_state_rdt(&ast);
+
+    gen.parse().unwrap()
+}
+
+// The generated `<Name>Operation` enum only derives the traits listed in an
+// `#[operation_derive(...)]` attribute on the struct, e.g. RustcEncodable and
+// RustcDecodable to send operations to other replicas.
+#[proc_macro_derive(OperationRDT, attributes(operation_derive))]
+pub fn derive_operation_rdt(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let gen = impl_operation_rdt(&ast);
+
+    gen.parse().unwrap()
+}
+
+fn struct_fields<'a>(ast: &'a DeriveInput, derive_name: &str) -> &'a [Field] {
+    match ast.body {
+        Body::Struct(VariantData::Struct(ref fields)) => fields,
+        _ => panic!("#[derive({})] is only supported on structs with named fields",
+                    derive_name),
+    }
+}
+
+fn operation_derives(ast: &DeriveInput) -> Vec<Ident> {
+    let mut derives = Vec::new();
+
+    for attr in &ast.attrs {
+        match attr.value {
+            MetaItem::List(ref name, ref items) if name == "operation_derive" => {
+                for item in items {
+                    match *item {
+                        NestedMetaItem::MetaItem(MetaItem::Word(ref derive)) => derives.push(derive.clone()),
+                        _ => panic!("#[operation_derive(...)] only accepts trait names"),
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    derives
+}
+
+fn variant_name(field: &Ident) -> Ident {
+    let mut name = String::new();
+    let mut upper = true;
+
+    for c in field.as_ref().chars() {
+        if c == '_' {
+            upper = true;
+        }
+        else if upper {
+            name.extend(c.to_uppercase());
+            upper = false;
+        }
+        else {
+            name.push(c);
+        }
+    }
+
+    Ident::new(name)
+}
+
+fn impl_state_rdt(ast: &DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let fields = struct_fields(ast, "StateRDT");
+    let self_fields = fields.iter().map(|f| f.ident.as_ref().unwrap());
+    let other_fields = fields.iter().map(|f| f.ident.as_ref().unwrap());
+
+    quote! {
+        impl #impl_generics ::crdt::StateRDT for #name #ty_generics #where_clause {
+            fn merge(&mut self, other: &Self) {
+                #( ::crdt::StateRDT::merge(&mut self.#self_fields, &other.#other_fields); )*
+            }
+        }
+    }
+}
+
+fn impl_operation_rdt(ast: &DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let vis = &ast.vis;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let fields = struct_fields(ast, "OperationRDT");
+    let op_name = Ident::new(format!("{}Operation", name));
+
+    let variants = fields.iter()
+        .map(|f| variant_name(f.ident.as_ref().unwrap()))
+        .collect::<Vec<_>>();
+    let field_names = fields.iter().map(|f| f.ident.as_ref().unwrap());
+    let field_tys = fields.iter().map(|f| &f.ty);
+
+    let decl_variants = &variants;
+    let match_variants = &variants;
+    let op_names = std::iter::repeat(&op_name).take(variants.len());
+
+    let derives = operation_derives(ast);
+
+    // The variants only use the type parameters through projections, which
+    // do not count as uses, so generic structs get a variant that never
+    // carries an operation.
+    let generic = !ast.generics.lifetimes.is_empty() || !ast.generics.ty_params.is_empty();
+
+    let (phantom_variant, phantom_arm) = if generic {
+        (quote! { #[doc(hidden)] __Phantom(::std::marker::PhantomData<#name #ty_generics>), },
+         quote! { &#op_name::__Phantom(_) => {}, })
+    }
+    else {
+        (quote! {}, quote! {})
+    };
+
+    quote! {
+        #[derive(#( #derives ),*)]
+        #vis enum #op_name #impl_generics #where_clause {
+            #( #decl_variants(<#field_tys as ::crdt::OperationRDT>::Operation), )*
+            #phantom_variant
+        }
+
+        impl #impl_generics ::crdt::OperationRDT for #name #ty_generics #where_clause {
+            type Operation = #op_name #ty_generics;
+
+            fn apply(&mut self, op: &Self::Operation) {
+                match op {
+                    #( &#op_names::#match_variants(ref op) =>
+                           ::crdt::OperationRDT::apply(&mut self.#field_names, op), )*
+                    #phantom_arm
+                }
+            }
+        }
+    }
+}
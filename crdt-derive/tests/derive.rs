@@ -0,0 +1,116 @@
+extern crate crdt;
+#[macro_use]
+extern crate crdt_derive;
+extern crate rustc_serialize;
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::iter::FromIterator;
+
+use crdt::{StateRDT, OperationRDT, AtomicReplica, LWWRegister, ObserveRemoveSet, PNCounter};
+
+#[derive(StateRDT, OperationRDT)]
+#[operation_derive(RustcEncodable, RustcDecodable)]
+struct Profile {
+    display_name: LWWRegister<String>,
+    tags: ObserveRemoveSet<String>,
+    visits: PNCounter<&'static str, i32>,
+}
+
+impl Profile {
+    fn new(host: &'static str) -> Profile {
+        Profile {
+            display_name: LWWRegister::new(),
+            tags: ObserveRemoveSet::new(),
+            visits: PNCounter::new(host),
+        }
+    }
+}
+
+#[derive(StateRDT, OperationRDT)]
+#[operation_derive(Debug, Clone)]
+struct Tally<H: Hash + Eq + Clone> {
+    hosts: ObserveRemoveSet<H>,
+    visits: PNCounter<H, i32>,
+}
+
+#[derive(Clone, OperationRDT)]
+#[operation_derive(Clone)]
+struct Team {
+    members: ObserveRemoveSet<&'static str>,
+    total: PNCounter<&'static str, i32>,
+}
+
+#[test]
+fn merge_profile_fields() {
+    let mut p1 = Profile::new("h1");
+    let mut p2 = Profile::new("h2");
+
+    p1.tags.add("admin".to_owned());
+    p1.visits.add(3);
+    p2.display_name.set("Alice".to_owned());
+    p2.visits.add(2);
+
+    p1.merge(&p2);
+    p2.merge(&p1);
+
+    for p in &[p1, p2] {
+        assert_eq!(p.display_name.value(), "Alice");
+        assert_eq!(p.tags.value(), HashSet::from_iter(vec!["admin".to_owned()]));
+        assert_eq!(p.visits.value(), 5);
+    }
+}
+
+#[test]
+fn apply_profile_operations() {
+    let mut p1 = Profile::new("h1");
+    let mut p2 = Profile::new("h2");
+
+    let ops = vec![
+        ProfileOperation::DisplayName(p1.display_name.set("Bob".to_owned())),
+        ProfileOperation::Tags(p1.tags.add("editor".to_owned())),
+        ProfileOperation::Visits(p1.visits.add(-1).unwrap()),
+    ];
+
+    for op in &ops {
+        p2.apply(op);
+    }
+
+    assert_eq!(p2.display_name.value(), "Bob");
+    assert_eq!(p2.tags.value(), HashSet::from_iter(vec!["editor".to_owned()]));
+    assert_eq!(p2.visits.value(), -1);
+}
+
+#[test]
+fn apply_generic_struct_operations() {
+    let mut t1 = Tally { hosts: ObserveRemoveSet::new(), visits: PNCounter::new("h1") };
+    let mut t2 = Tally { hosts: ObserveRemoveSet::new(), visits: PNCounter::new("h2") };
+
+    let op = TallyOperation::Hosts(t1.hosts.add("h1"));
+
+    t2.apply(&op.clone());
+    t2.apply(&TallyOperation::Visits(t1.visits.add(3).unwrap()));
+
+    assert_eq!(t2.hosts.value(), HashSet::from_iter(vec!["h1"]));
+    assert_eq!(t2.visits.value(), 3);
+}
+
+#[test]
+fn apply_atomic_struct_transaction() {
+    let mut r1 = AtomicReplica::new(Team { members: ObserveRemoveSet::new(), total: PNCounter::new("h1") });
+    let mut r2 = AtomicReplica::new(Team { members: ObserveRemoveSet::new(), total: PNCounter::new("h2") });
+
+    let op = {
+        let mut tx = r1.transaction();
+
+        tx.update(|t| Some(TeamOperation::Members(t.members.add("alice"))))
+            .update(|t| t.total.add(2).map(TeamOperation::Total));
+
+        tx.commit().unwrap()
+    };
+
+    r2.apply(&op);
+
+    assert_eq!(r2.value().members.value(), HashSet::from_iter(vec!["alice"]));
+    assert_eq!(r2.value().total.value(), 2);
+}
@@ -0,0 +1,424 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::hash::Hash;
+
+use uuid::Uuid;
+
+use core::{StateRDT, OperationRDT, ValueFactory, DiffRDT};
+use merkle::{MerkleTree, Digest, item_hash, in_buckets};
+
+// Each replica keeps one slot per element. Its increments and decrements
+// only grow, and a removal records how much of them it observed, so every
+// field merges by taking the maximum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Hash, RustcEncodable, RustcDecodable)]
+struct BagSlot {
+    pos: u64,
+    neg: u64,
+    removed_pos: u64,
+    removed_neg: u64,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct ORBag<T: Hash + Eq + Clone> {
+    id: Uuid,
+    members: HashMap<T, HashMap<Uuid, BagSlot>>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum ORBagOperation<T> {
+    Add(T, Uuid, u64, u64),
+    Remove(T, HashMap<Uuid, (u64, u64)>),
+}
+
+pub struct Iter<'a, T: 'a> {
+    members: hash_map::Iter<'a, T, HashMap<Uuid, BagSlot>>,
+}
+
+impl BagSlot {
+    fn count(&self) -> i64 {
+        (self.pos - self.removed_pos) as i64 - (self.neg - self.removed_neg) as i64
+    }
+
+    fn join(&mut self, other: &BagSlot) {
+        self.pos = cmp::max(self.pos, other.pos);
+        self.neg = cmp::max(self.neg, other.neg);
+        self.removed_pos = cmp::max(self.removed_pos, other.removed_pos);
+        self.removed_neg = cmp::max(self.removed_neg, other.removed_neg);
+    }
+}
+
+fn raw_count(slots: &HashMap<Uuid, BagSlot>) -> i64 {
+    slots.values().map(|slot| slot.count()).sum()
+}
+
+// Concurrent decrements can take the sum below zero, which counts as absent.
+// The next local increment pays the shortfall off first, see `ORBag::add`.
+fn live_count(slots: &HashMap<Uuid, BagSlot>) -> i64 {
+    cmp::max(0, raw_count(slots))
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a T, i64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((value, slots)) = self.members.next() {
+            let count = live_count(slots);
+
+            if count != 0 {
+                return Some((value, count));
+            }
+        }
+
+        None
+    }
+}
+
+impl<T: Hash + Eq + Clone> ORBag<T> {
+    pub fn new() -> ORBag<T> {
+        ORBag {
+            id: Uuid::new_v4(),
+            members: HashMap::new(),
+        }
+    }
+
+    pub fn count(&self, value: &T) -> i64 {
+        self.members.get(value).map_or(0, live_count)
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a, T> {
+        Iter {
+            members: self.members.iter(),
+        }
+    }
+
+    pub fn total(&self) -> i64 {
+        self.iter().map(|(_, count)| count).sum()
+    }
+
+    // Decrements below zero are rejected. An increment on a value whose
+    // count went below zero also covers the shortfall, so the value ends up
+    // with exactly `delta`.
+    pub fn add(&mut self, value: T, delta: i64) -> Option<ORBagOperation<T>> {
+        if delta == 0 || self.count(&value) + delta < 0 {
+            return None;
+        }
+
+        let shortfall = cmp::max(0, -self.members.get(&value).map_or(0, raw_count));
+        let mut slot = self.slot(&value, &self.id);
+
+        if delta > 0 {
+            slot.pos += (delta + shortfall) as u64;
+        }
+        else {
+            slot.neg += (-delta) as u64;
+        }
+
+        let op = ORBagOperation::Add(value, self.id.clone(), slot.pos, slot.neg);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    // Removal only cancels the counts observed here, so increments made
+    // concurrently on other replicas survive it. A value whose count went
+    // below zero can still be removed, which clears the shortfall.
+    pub fn remove(&mut self, value: T) -> Option<ORBagOperation<T>> {
+        let live = self.members.get(&value)
+            .map_or(false, |slots| slots.values().any(|slot| slot.count() != 0));
+
+        if !live {
+            return None;
+        }
+
+        let observed = self.members[&value].iter()
+            .map(|(id, slot)| (id.clone(), (slot.pos, slot.neg)))
+            .collect();
+
+        let op = ORBagOperation::Remove(value, observed);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    fn slot(&self, value: &T, id: &Uuid) -> BagSlot {
+        self.members.get(value)
+            .and_then(|slots| slots.get(id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn join_slot(&mut self, value: &T, id: &Uuid, slot: &BagSlot) {
+        self.members.entry(value.clone())
+            .or_insert(HashMap::new())
+            .entry(id.clone())
+            .or_insert(BagSlot::default())
+            .join(slot);
+    }
+}
+
+impl<T: Hash + Eq + Clone> OperationRDT for ORBag<T> {
+    type Operation = ORBagOperation<T>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        use self::ORBagOperation::{Add, Remove};
+
+        match op {
+            &Add(ref value, ref id, pos, neg) => {
+                let slot = BagSlot { pos: pos, neg: neg, ..BagSlot::default() };
+
+                self.join_slot(value, id, &slot);
+            },
+            &Remove(ref value, ref observed) => {
+                for (id, &(pos, neg)) in observed {
+                    let slot = BagSlot { pos: pos, neg: neg, removed_pos: pos, removed_neg: neg };
+
+                    self.join_slot(value, id, &slot);
+                }
+            },
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> StateRDT for ORBag<T> {
+    fn merge(&mut self, other: &Self) {
+        for (value, slots) in &other.members {
+            for (id, slot) in slots {
+                self.join_slot(value, id, slot);
+            }
+        }
+    }
+}
+
+// Every slot is an item of the summary tree; a changed slot hashes
+// differently and is sent again in full.
+impl<T: Hash + Eq + Clone> DiffRDT for ORBag<T> {
+    type Summary = MerkleTree;
+    type Delta = ORBag<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.members.iter()
+            .flat_map(|(value, slots)| {
+                slots.iter().map(move |(id, slot)| item_hash(&(value, id, slot)))
+            })
+            .collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().diff(summary);
+        let mut delta = ORBag::new();
+
+        for (value, slots) in &self.members {
+            for (id, slot) in slots {
+                if in_buckets(&buckets, item_hash(&(value, id, slot))) {
+                    delta.join_slot(value, id, slot);
+                }
+            }
+        }
+
+        delta
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
+    }
+}
+
+impl<T: Hash + Eq + Clone> Digest for ORBag<T> {
+    fn digest(&self) -> u64 {
+        self.summary().root()
+    }
+}
+
+impl<K, T: Hash + Eq + Clone> ValueFactory<K> for ORBag<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        ORBag::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ORBag;
+
+    use std::collections::HashMap;
+    use std::iter::FromIterator;
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    #[test]
+    fn make_or_bag() {
+        let bag: ORBag<&str> = ORBag::new();
+
+        assert_eq!(bag.count(&"apple"), 0);
+        assert_eq!(bag.total(), 0);
+    }
+
+    #[test]
+    fn add_or_bag() {
+        let mut bag = ORBag::new();
+
+        bag.add("apple", 3).unwrap();
+        bag.add("pear", 1).unwrap();
+        bag.add("apple", -1).unwrap();
+
+        assert!(bag.add("pear", 0).is_none());
+        assert_eq!(bag.count(&"apple"), 2);
+        assert_eq!(bag.total(), 3);
+        assert_eq!(bag.iter().collect::<HashMap<_, _>>(),
+                   HashMap::from_iter(vec![(&"apple", 2), (&"pear", 1)]));
+    }
+
+    #[test]
+    fn remove_or_bag() {
+        let mut bag = ORBag::new();
+
+        bag.add("apple", 3).unwrap();
+        bag.remove("apple").unwrap();
+
+        assert!(bag.remove("apple").is_none());
+        assert_eq!(bag.count(&"apple"), 0);
+        assert_eq!(bag.iter().count(), 0);
+
+        bag.add("apple", 1).unwrap();
+
+        assert_eq!(bag.count(&"apple"), 1);
+    }
+
+    #[test]
+    fn apply_concurrent_add_and_remove() {
+        let mut b1 = ORBag::new();
+        let mut b2 = ORBag::new();
+
+        let op1 = b1.add("apple", 2).unwrap();
+        b2.apply(&op1);
+
+        let op2 = b1.remove("apple").unwrap();
+        let op3 = b2.add("apple", 5).unwrap();
+
+        b1.apply(&op3);
+        b2.apply(&op2);
+
+        assert_eq!(b1.count(&"apple"), 5);
+        assert_eq!(b2.count(&"apple"), 5);
+    }
+
+    #[test]
+    fn apply_remove_concurrent_with_own_increment() {
+        let mut b1 = ORBag::new();
+        let mut b2 = ORBag::new();
+
+        let op1 = b1.add("apple", 2).unwrap();
+        b2.apply(&op1);
+
+        let op2 = b2.remove("apple").unwrap();
+        let op3 = b1.add("apple", 5).unwrap();
+
+        b1.apply(&op2);
+        b2.apply(&op3);
+
+        assert_eq!(b1.count(&"apple"), 5);
+        assert_eq!(b2.count(&"apple"), 5);
+    }
+
+    #[test]
+    fn bound_or_bag_counts() {
+        let mut b1 = ORBag::new();
+        let mut b2 = ORBag::new();
+
+        for _ in 0..100 {
+            b1.add("apple", 1).unwrap();
+        }
+
+        assert_eq!(b1.members[&"apple"].len(), 1);
+        assert!(b1.add("pear", -1).is_none());
+
+        b2.merge(&b1);
+        b1.add("apple", -100).unwrap();
+        b2.add("apple", -60).unwrap();
+
+        b1.merge(&b2);
+
+        assert_eq!(b1.count(&"apple"), 0);
+        assert_eq!(b1.iter().count(), 0);
+        assert_eq!(b1.members[&"apple"].len(), 2);
+    }
+
+    #[test]
+    fn add_after_or_bag_overdraft() {
+        let mut b1 = ORBag::new();
+        let mut b2 = ORBag::new();
+
+        b2.apply(&b1.add("apple", 1).unwrap());
+
+        b1.add("apple", -1).unwrap();
+        b2.add("apple", -1).unwrap();
+        b1.merge(&b2);
+        b2.merge(&b1);
+
+        assert_eq!(b1.count(&"apple"), 0);
+
+        b2.apply(&b1.add("apple", 2).unwrap());
+
+        assert_eq!(b1.count(&"apple"), 2);
+        assert_eq!(b2.count(&"apple"), 2);
+
+        b1.add("apple", -2).unwrap();
+        b2.add("apple", -2).unwrap();
+        b2.merge(&b1);
+
+        assert_eq!(b2.count(&"apple"), 0);
+
+        let op = b2.remove("apple").unwrap();
+        b2.add("apple", 1).unwrap();
+
+        assert_eq!(b2.count(&"apple"), 1);
+
+        b1.apply(&op);
+
+        assert_eq!(b1.count(&"apple"), 0);
+    }
+
+    #[test]
+    fn merge_or_bag_state() {
+        let mut b1 = ORBag::new();
+        let mut b2 = ORBag::new();
+
+        b1.add("apple", 2).unwrap();
+        b2.merge(&b1);
+
+        b1.add("apple", 1).unwrap();
+        b2.add("apple", -1).unwrap();
+        b2.add("pear", 4).unwrap();
+
+        b1.merge(&b2);
+        b2.merge(&b1);
+
+        assert_eq!(b1.count(&"apple"), 2);
+        assert_eq!(b2.count(&"apple"), 2);
+        assert_eq!(b1.total(), 6);
+        assert_eq!(b2.total(), 6);
+    }
+
+    #[test]
+    fn diff_or_bag_state() {
+        let mut b1 = ORBag::new();
+        let mut b2 = ORBag::new();
+
+        b1.add("apple", 2).unwrap();
+        b2.merge(&b1);
+
+        b1.add("apple", 1).unwrap();
+        b2.add("pear", 4).unwrap();
+
+        b1.apply_diff(&b2.diff(&b1.summary()));
+        b2.apply_diff(&b1.diff(&b2.summary()));
+
+        assert_eq!(b1.count(&"apple"), 3);
+        assert_eq!(b2.count(&"apple"), 3);
+        assert_eq!(b1.summary(), b2.summary());
+    }
+}
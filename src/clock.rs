@@ -0,0 +1,162 @@
+use std::cmp::{self, Ordering};
+
+use uuid::Uuid;
+
+use core::Lattice;
+use lattices::{MapLattice, MaxLattice};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, RustcEncodable, RustcDecodable)]
+pub struct LamportTimestamp {
+    counter: u64,
+    id: Uuid,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct LamportClock {
+    counter: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct VersionVector {
+    counters: MapLattice<Uuid, MaxLattice<u64>>,
+}
+
+impl LamportTimestamp {
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+}
+
+impl LamportClock {
+    pub fn new() -> LamportClock {
+        LamportClock {
+            counter: 0,
+        }
+    }
+
+    pub fn tick(&mut self) -> LamportTimestamp {
+        self.counter += 1;
+
+        LamportTimestamp {
+            counter: self.counter,
+            id: Uuid::new_v4(),
+        }
+    }
+
+    pub fn observe(&mut self, timestamp: &LamportTimestamp) {
+        self.observe_counter(timestamp.counter);
+    }
+
+    pub fn observe_counter(&mut self, counter: u64) {
+        if counter > self.counter {
+            self.counter = counter;
+        }
+    }
+}
+
+impl VersionVector {
+    pub fn new() -> VersionVector {
+        VersionVector {
+            counters: MapLattice::new(),
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> u64 {
+        self.counters.get(id).map_or(0, |c| *c.value())
+    }
+
+    pub fn contains(&self, id: &Uuid, counter: u64) -> bool {
+        counter <= self.get(id)
+    }
+
+    pub fn increment(&mut self, id: &Uuid) -> u64 {
+        let counter = self.get(id) + 1;
+
+        self.observe(id, counter);
+
+        counter
+    }
+
+    pub fn observe(&mut self, id: &Uuid, counter: u64) {
+        if counter > 0 {
+            self.counters.insert(id.clone(), MaxLattice::new(counter));
+        }
+    }
+
+    pub fn meet(&self, other: &VersionVector) -> VersionVector {
+        let mut result = VersionVector::new();
+
+        for (id, counter) in self.counters.iter() {
+            result.observe(id, cmp::min(*counter.value(), other.get(id)));
+        }
+
+        result
+    }
+}
+
+impl Lattice for VersionVector {
+    fn join(&mut self, other: &Self) {
+        self.counters.join(&other.counters);
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        self.counters.compare(&other.counters)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LamportClock, VersionVector};
+
+    use std::cmp::Ordering;
+
+    use uuid::Uuid;
+
+    use core::Lattice;
+
+    #[test]
+    fn tick_lamport_clock() {
+        let mut clock = LamportClock::new();
+
+        let t1 = clock.tick();
+        let t2 = clock.tick();
+
+        assert!(t1 < t2);
+    }
+
+    #[test]
+    fn observe_lamport_clock() {
+        let mut c1 = LamportClock::new();
+        let mut c2 = LamportClock::new();
+
+        c1.tick();
+        let t1 = c1.tick();
+
+        c2.observe(&t1);
+        let t2 = c2.tick();
+
+        assert!(t1 < t2);
+        assert_eq!(t2.counter(), 3);
+    }
+
+    #[test]
+    fn join_version_vectors() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut v1 = VersionVector::new();
+        let mut v2 = VersionVector::new();
+
+        v1.increment(&a);
+        v1.increment(&a);
+        v2.increment(&b);
+
+        assert_eq!(v1.compare(&v2), None);
+        assert_eq!(v1.meet(&v2), VersionVector::new());
+
+        v2.join(&v1);
+
+        assert_eq!(v2.compare(&v1), Some(Ordering::Greater));
+        assert_eq!(v2.get(&a), 2);
+        assert!(v2.contains(&b, 1));
+        assert!(!v2.contains(&b, 2));
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 pub trait StateRDT {
     fn merge(&mut self, other: &Self);
 }
@@ -7,3 +9,84 @@ pub trait OperationRDT {
 
     fn apply(&mut self, op: &Self::Operation);
 }
+
+pub trait BatchRDT: OperationRDT {
+    type Batch;
+
+    fn batch(ops: Vec<Self::Operation>) -> Option<Self::Batch>;
+
+    fn apply_batch(&mut self, batch: &Self::Batch);
+}
+
+// Summaries are small descriptions of a replica's state; `diff` answers
+// one with just what that replica is missing.
+pub trait DiffRDT {
+    type Summary;
+    type Delta;
+
+    fn summary(&self) -> Self::Summary;
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta;
+
+    fn apply_diff(&mut self, delta: &Self::Delta);
+}
+
+pub trait Lattice {
+    fn join(&mut self, other: &Self);
+
+    fn compare(&self, other: &Self) -> Option<Ordering>;
+}
+
+// Lattices whose elements are totally ordered, so `compare` never returns
+// `None`.
+pub trait Chain: Lattice {}
+
+pub trait ValueFactory<K> {
+    type Context: Clone;
+
+    fn create(key: &K, context: &Self::Context) -> Self;
+}
+
+// Updates run against a staged copy of the target, which only takes the
+// batch in `commit`; a builder dropped before then changes nothing.
+pub struct BatchBuilder<'a, T: BatchRDT + 'a> {
+    target: &'a mut T,
+    staged: T,
+    ops: Vec<T::Operation>,
+}
+
+impl<'a, T: BatchRDT + Clone> BatchBuilder<'a, T> {
+    pub fn new(target: &'a mut T) -> BatchBuilder<'a, T> {
+        BatchBuilder {
+            staged: target.clone(),
+            target: target,
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn update<F>(&mut self, update_fn: F) -> &mut Self
+        where F: FnOnce(&mut T) -> Option<T::Operation>
+    {
+        if let Some(op) = update_fn(&mut self.staged) {
+            self.ops.push(op);
+        }
+
+        self
+    }
+
+    pub fn commit(self) -> Option<T::Batch> {
+        let batch = T::batch(self.ops);
+
+        if let Some(ref batch) = batch {
+            self.target.apply_batch(batch);
+        }
+
+        batch
+    }
+}
+
+impl<L: Lattice> StateRDT for L {
+    fn merge(&mut self, other: &Self) {
+        self.join(other);
+    }
+}
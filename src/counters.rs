@@ -1,26 +1,30 @@
 use std::num::Zero;
 use std::ops::{Add, Sub, Neg};
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use std::hash::Hash;
 
-use core::{StateRDT, OperationRDT};
+use core::{StateRDT, OperationRDT, Lattice, ValueFactory, BatchRDT, DiffRDT};
+use lattices::{MapLattice, MaxLattice};
+use merkle::{Digest, item_hash};
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+use chrono::{DateTime, Duration, UTC};
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct GCounter<HostT, ValueT>
     where HostT: Hash + Eq + Clone,
           ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy
 {
     my_id: HostT,
-    counters: HashMap<HostT, ValueT>,
+    counters: MapLattice<HostT, MaxLattice<ValueT>>,
 }
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct SetGCounterOperation<HostT, ValueT> {
     id: HostT,
     value: ValueT,
 }
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct PNCounter<HostT, ValueT>
     where HostT: Hash + Eq + Clone,
           ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy
@@ -30,26 +34,44 @@ pub struct PNCounter<HostT, ValueT>
     neg_counters: HashMap<HostT, ValueT>,
 }
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct SetPNCounterOperation<HostT, ValueT> {
     id: HostT,
     pos_value: ValueT,
     neg_value: ValueT,
 }
 
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct WindowedCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy
+{
+    my_id: HostT,
+    bucket_width: i64,
+    retention: i64,
+    buckets: BTreeMap<i64, MapLattice<HostT, MaxLattice<ValueT>>>,
+}
+
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub struct SetWindowedCounterOperation<HostT, ValueT> {
+    bucket: i64,
+    id: HostT,
+    value: ValueT,
+}
+
 impl<HostT, ValueT> GCounter<HostT, ValueT>
     where HostT: Hash + Eq + Clone,
           ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy
 {
     pub fn new(my_id: HostT) -> Self {
         GCounter {
-            counters: HashMap::new(),
+            counters: MapLattice::new(),
             my_id: my_id,
         }
     }
 
     pub fn value(&self) -> ValueT {
-        self.counters.values().cloned().sum()
+        self.counters.values().map(|v| *v.value()).sum()
     }
 
     pub fn add(&mut self, value: ValueT)
@@ -59,9 +81,11 @@ impl<HostT, ValueT> GCounter<HostT, ValueT>
             return None;
         }
 
+        let current = self.counters.get(&self.my_id).map_or(Zero::zero(), |v| *v.value());
+
         let op = SetGCounterOperation {
             id: self.my_id.clone(),
-            value: self.value() + value,
+            value: current + value,
         };
 
         self.apply(&op);
@@ -95,18 +119,20 @@ impl<HostT, ValueT> PNCounter<HostT, ValueT>
     pub fn add(&mut self, value: ValueT)
         -> Option<SetPNCounterOperation<HostT, ValueT>>
     {
+        let (pos_value, neg_value) = self.slot(&self.my_id);
+
         let op = if value >= Zero::zero() {
             SetPNCounterOperation {
                 id: self.my_id.clone(),
-                pos_value: value,
-                neg_value: Zero::zero(),
+                pos_value: pos_value + value,
+                neg_value: neg_value,
             }
         }
         else {
             SetPNCounterOperation {
                 id: self.my_id.clone(),
-                pos_value: Zero::zero(),
-                neg_value: -value
+                pos_value: pos_value,
+                neg_value: neg_value - value,
             }
         };
 
@@ -114,6 +140,91 @@ impl<HostT, ValueT> PNCounter<HostT, ValueT>
 
         Some(op)
     }
+
+    fn slot(&self, id: &HostT) -> (ValueT, ValueT) {
+        (self.pos_counters.get(id).cloned().unwrap_or(Zero::zero()),
+         self.neg_counters.get(id).cloned().unwrap_or(Zero::zero()))
+    }
+}
+
+impl<HostT, ValueT> WindowedCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy
+{
+    pub fn new(my_id: HostT, bucket_width: Duration, retention: usize) -> Self {
+        assert!(bucket_width.num_seconds() > 0, "bucket width must be at least one second");
+        assert!(retention > 0, "retention must be at least one bucket");
+
+        WindowedCounter {
+            my_id: my_id,
+            bucket_width: bucket_width.num_seconds(),
+            retention: retention as i64,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    pub fn value(&self) -> ValueT {
+        self.buckets.values()
+            .flat_map(|bucket| bucket.values())
+            .map(|v| *v.value())
+            .sum()
+    }
+
+    pub fn sum(&self, now: DateTime<UTC>, buckets: usize) -> ValueT {
+        let last = self.bucket(now);
+        let first = last - buckets as i64 + 1;
+
+        self.buckets.range(first..last + 1)
+            .flat_map(|(_, bucket)| bucket.values())
+            .map(|v| *v.value())
+            .sum()
+    }
+
+    pub fn add(&mut self, value: ValueT, now: DateTime<UTC>)
+        -> Option<SetWindowedCounterOperation<HostT, ValueT>>
+    {
+        if value < Zero::zero() {
+            return None;
+        }
+
+        self.expire(now);
+
+        let bucket = self.bucket(now);
+        let current = self.buckets.get(&bucket)
+            .and_then(|b| b.get(&self.my_id))
+            .map_or(Zero::zero(), |v| *v.value());
+
+        let op = SetWindowedCounterOperation {
+            bucket: bucket,
+            id: self.my_id.clone(),
+            value: current + value,
+        };
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    fn bucket(&self, time: DateTime<UTC>) -> i64 {
+        let seconds = time.timestamp();
+
+        if seconds >= 0 {
+            seconds / self.bucket_width
+        }
+        else {
+            (seconds + 1) / self.bucket_width - 1
+        }
+    }
+
+    // Buckets are expired against the local clock rather than the newest
+    // bucket seen, so a peer whose clock runs ahead cannot wipe out everyone
+    // else's counts. Merging never drops buckets; old ones sent back by a
+    // peer are dropped again on the next expiry.
+    pub fn expire(&mut self, now: DateTime<UTC>) {
+        let horizon = self.bucket(now) - self.retention + 1;
+
+        self.buckets = self.buckets.split_off(&horizon);
+    }
 }
 
 impl<HostT, ValueT>
@@ -125,12 +236,7 @@ impl<HostT, ValueT>
     type Operation = SetGCounterOperation<HostT, ValueT>;
 
     fn apply(&mut self, op: &Self::Operation) {
-        let cur_value = self.counters.get(&op.id).cloned()
-            .unwrap_or(Zero::zero());
-
-        self.counters.insert(
-            op.id.clone(),
-            *vec![op.value, cur_value].iter().max().unwrap());
+        self.counters.insert(op.id.clone(), MaxLattice::new(op.value));
     }
 }
 
@@ -141,14 +247,7 @@ impl<HostT, ValueT>
           ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
 {
     fn merge(&mut self, other: &Self) {
-        for (id, &value) in &other.counters {
-            let cur_value = self.counters.get(id).cloned()
-                .unwrap_or(Zero::zero());
-
-            self.counters.insert(
-                id.clone(),
-                *vec![cur_value, value].iter().max().unwrap());
-        }
+        self.counters.join(&other.counters);
     }
 }
 
@@ -208,10 +307,273 @@ impl<HostT, ValueT>
     }
 }
 
+impl<HostT, ValueT>
+    OperationRDT
+    for WindowedCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
+{
+    type Operation = SetWindowedCounterOperation<HostT, ValueT>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        self.buckets.entry(op.bucket)
+            .or_insert(MapLattice::new())
+            .insert(op.id.clone(), MaxLattice::new(op.value));
+    }
+}
+
+impl<HostT, ValueT>
+    StateRDT
+    for WindowedCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
+{
+    fn merge(&mut self, other: &Self) {
+        for (bucket, counters) in &other.buckets {
+            self.buckets.entry(*bucket)
+                .or_insert(MapLattice::new())
+                .join(counters);
+        }
+    }
+}
+
+// Counter operations carry the absolute value of a replica's slot, so a
+// batch only needs the largest operation seen for each replica.
+impl<HostT, ValueT>
+    BatchRDT
+    for GCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
+{
+    type Batch = Vec<SetGCounterOperation<HostT, ValueT>>;
+
+    fn batch(ops: Vec<Self::Operation>) -> Option<Self::Batch> {
+        let mut latest: HashMap<HostT, SetGCounterOperation<HostT, ValueT>> = HashMap::new();
+
+        for op in ops {
+            let replace = latest.get(&op.id).map_or(true, |cur| op.value > cur.value);
+
+            if replace {
+                latest.insert(op.id.clone(), op);
+            }
+        }
+
+        if latest.is_empty() {
+            None
+        }
+        else {
+            Some(latest.into_iter().map(|(_, op)| op).collect())
+        }
+    }
+
+    fn apply_batch(&mut self, batch: &Self::Batch) {
+        for op in batch {
+            self.apply(op);
+        }
+    }
+}
+
+impl<HostT, ValueT>
+    BatchRDT
+    for PNCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> +
+                  Sub<ValueT, Output=ValueT> +
+                  Neg<Output=ValueT> +
+                  Zero + Ord + Copy
+{
+    type Batch = Vec<SetPNCounterOperation<HostT, ValueT>>;
+
+    fn batch(ops: Vec<Self::Operation>) -> Option<Self::Batch> {
+        let mut latest: HashMap<HostT, SetPNCounterOperation<HostT, ValueT>> = HashMap::new();
+
+        for op in ops {
+            let combined = match latest.remove(&op.id) {
+                Some(cur) => SetPNCounterOperation {
+                    id: op.id,
+                    pos_value: *vec![cur.pos_value, op.pos_value].iter().max().unwrap(),
+                    neg_value: *vec![cur.neg_value, op.neg_value].iter().max().unwrap(),
+                },
+                None => op,
+            };
+
+            latest.insert(combined.id.clone(), combined);
+        }
+
+        if latest.is_empty() {
+            None
+        }
+        else {
+            Some(latest.into_iter().map(|(_, op)| op).collect())
+        }
+    }
+
+    fn apply_batch(&mut self, batch: &Self::Batch) {
+        for op in batch {
+            self.apply(op);
+        }
+    }
+}
+
+// A summary holds the peer's slot for every replica, so only the slots
+// that are ahead of it have to be sent.
+impl<HostT, ValueT>
+    DiffRDT
+    for GCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
+{
+    type Summary = HashMap<HostT, ValueT>;
+    type Delta = Vec<SetGCounterOperation<HostT, ValueT>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.counters.iter().map(|(id, value)| (id.clone(), *value.value())).collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.counters.iter()
+            .filter(|&(id, value)| summary.get(id).map_or(true, |theirs| value.value() > theirs))
+            .map(|(id, value)| SetGCounterOperation { id: id.clone(), value: *value.value() })
+            .collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.apply_batch(delta);
+    }
+}
+
+impl<HostT, ValueT>
+    DiffRDT
+    for PNCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> +
+                  Sub<ValueT, Output=ValueT> +
+                  Neg<Output=ValueT> +
+                  Zero + Ord + Copy
+{
+    type Summary = HashMap<HostT, (ValueT, ValueT)>;
+    type Delta = Vec<SetPNCounterOperation<HostT, ValueT>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.pos_counters.keys()
+            .chain(self.neg_counters.keys())
+            .map(|id| (id.clone(), self.slot(id)))
+            .collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.summary().into_iter()
+            .filter(|&(ref id, (pos, neg))| {
+                summary.get(id).map_or(true, |&(their_pos, their_neg)| pos > their_pos || neg > their_neg)
+            })
+            .map(|(id, (pos, neg))| SetPNCounterOperation { id: id, pos_value: pos, neg_value: neg })
+            .collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.apply_batch(delta);
+    }
+}
+
+impl<HostT, ValueT>
+    DiffRDT
+    for WindowedCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
+{
+    type Summary = HashMap<(i64, HostT), ValueT>;
+    type Delta = Vec<SetWindowedCounterOperation<HostT, ValueT>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.buckets.iter()
+            .flat_map(|(&bucket, counters)| {
+                counters.iter().map(move |(id, value)| ((bucket, id.clone()), *value.value()))
+            })
+            .collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.summary().into_iter()
+            .filter(|&(ref slot, value)| summary.get(slot).map_or(true, |&theirs| value > theirs))
+            .map(|((bucket, id), value)| SetWindowedCounterOperation { bucket: bucket, id: id, value: value })
+            .collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        for op in delta {
+            self.apply(op);
+        }
+    }
+}
+
+impl<K, HostT, ValueT>
+    ValueFactory<K>
+    for GCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Ord + Add<ValueT, Output=ValueT> + Zero + Copy
+{
+    type Context = HostT;
+
+    fn create(_: &K, my_id: &HostT) -> Self {
+        GCounter::new(my_id.clone())
+    }
+}
+
+impl<K, HostT, ValueT>
+    ValueFactory<K>
+    for PNCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> +
+                  Sub<ValueT, Output=ValueT> +
+                  Neg<Output=ValueT> +
+                  Zero + Ord + Copy
+{
+    type Context = HostT;
+
+    fn create(_: &K, my_id: &HostT) -> Self {
+        PNCounter::new(my_id.clone())
+    }
+}
+
+impl<HostT, ValueT> Digest for GCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy + Hash
+{
+    fn digest(&self) -> u64 {
+        self.counters.iter().fold(0, |acc, (id, value)| acc ^ item_hash(&(id, value.value())))
+    }
+}
+
+impl<HostT, ValueT> Digest for PNCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy + Hash
+{
+    fn digest(&self) -> u64 {
+        let pos = self.pos_counters.iter().fold(0, |acc, (id, value)| acc ^ item_hash(&(false, id, value)));
+
+        self.neg_counters.iter().fold(pos, |acc, (id, value)| acc ^ item_hash(&(true, id, value)))
+    }
+}
+
+impl<HostT, ValueT> Digest for WindowedCounter<HostT, ValueT>
+    where HostT: Hash + Eq + Clone,
+          ValueT: Add<ValueT, Output=ValueT> + Ord + Zero + Copy + Hash
+{
+    fn digest(&self) -> u64 {
+        self.summary().iter().fold(0, |acc, (slot, value)| acc ^ item_hash(&(slot, value)))
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{GCounter, PNCounter};
-    use core::{StateRDT, OperationRDT};
+    use super::{GCounter, PNCounter, WindowedCounter};
+    use core::{StateRDT, OperationRDT, BatchRDT, BatchBuilder, DiffRDT};
+
+    use chrono::{DateTime, Duration, UTC, TimeZone};
+
+    fn minute(m: i64) -> DateTime<UTC> {
+        UTC.timestamp(m * 60 + 30, 0)
+    }
 
     #[test]
     fn make_g_counter() {
@@ -252,6 +614,23 @@ mod test {
         assert_eq!(c2.value(), 12);
     }
 
+    #[test]
+    fn apply_repeated_g_counter_increments() {
+        let mut c1 = GCounter::new("h1");
+        let mut c2 = GCounter::new("h2");
+
+        c1.apply(&c2.add(7).unwrap());
+
+        let ops = vec![c1.add(5).unwrap(), c1.add(3).unwrap()];
+
+        for op in &ops {
+            c2.apply(op);
+        }
+
+        assert_eq!(c1.value(), 15);
+        assert_eq!(c2.value(), 15);
+    }
+
     #[test]
     fn merge_g_counter_state() {
         let mut c1 = GCounter::new("h1");
@@ -308,6 +687,21 @@ mod test {
         assert_eq!(c2.value(), -2);
     }
 
+    #[test]
+    fn apply_repeated_pn_counter_increments() {
+        let mut c1 = PNCounter::new("h1");
+        let mut c2 = PNCounter::new("h2");
+
+        let ops = vec![c1.add(5).unwrap(), c1.add(3).unwrap(), c1.add(-2).unwrap()];
+
+        for op in &ops {
+            c2.apply(op);
+        }
+
+        assert_eq!(c1.value(), 6);
+        assert_eq!(c2.value(), 6);
+    }
+
     #[test]
     fn merge_pn_counter_state() {
         let mut c1 = PNCounter::new("h1");
@@ -322,4 +716,169 @@ mod test {
         assert_eq!(c1.value(), -2);
         assert_eq!(c2.value(), -2);
     }
+
+    #[test]
+    fn diff_pn_counter_state() {
+        let mut c1 = PNCounter::new("h1");
+        let mut c2 = PNCounter::new("h2");
+
+        c1.add(5);
+        c2.add(-7);
+
+        let delta = c2.diff(&c1.summary());
+
+        assert_eq!(delta.len(), 1);
+        assert!(c2.diff(&c2.summary()).is_empty());
+
+        c1.apply_diff(&delta);
+
+        assert_eq!(c1.value(), -2);
+    }
+
+    #[test]
+    fn apply_g_counter_batch() {
+        let mut c1 = GCounter::new("h1");
+        let mut c2 = GCounter::new("h2");
+
+        let batch = {
+            let mut builder = BatchBuilder::new(&mut c1);
+
+            for _ in 0..3 {
+                builder.update(|c| c.add(2));
+            }
+
+            builder.commit().unwrap()
+        };
+
+        c2.apply_batch(&batch);
+
+        assert_eq!(batch.len(), 1);
+        assert_eq!(c2.value(), c1.value());
+    }
+
+    #[test]
+    fn apply_pn_counter_batch() {
+        let mut c1 = PNCounter::new("h1");
+        let mut c2 = PNCounter::new("h2");
+
+        let batch = {
+            let mut builder = BatchBuilder::new(&mut c1);
+
+            builder.update(|c| c.add(5)).update(|c| c.add(-3));
+
+            builder.commit().unwrap()
+        };
+
+        c2.apply_batch(&batch);
+
+        assert_eq!(batch.len(), 1);
+        assert_eq!(c1.value(), 2);
+        assert_eq!(c2.value(), 2);
+        assert!(BatchBuilder::new(&mut c2).commit().is_none());
+    }
+
+    #[test]
+    fn drop_uncommitted_batch() {
+        let mut c1 = GCounter::new("h1");
+
+        c1.add(1).unwrap();
+
+        {
+            let mut builder = BatchBuilder::new(&mut c1);
+
+            builder.update(|c| c.add(2)).update(|c| c.add(3));
+        }
+
+        assert_eq!(c1.value(), 1);
+    }
+
+    #[test]
+    fn make_windowed_counter() {
+        let counter: WindowedCounter<&'static str, i32> =
+            WindowedCounter::new("h1", Duration::minutes(1), 10);
+
+        assert_eq!(counter.value(), 0);
+        assert_eq!(counter.sum(minute(0), 5), 0);
+    }
+
+    #[test]
+    fn add_windowed_counter() {
+        let mut counter = WindowedCounter::new("h1", Duration::minutes(1), 10);
+
+        counter.add(3, minute(0)).unwrap();
+        counter.add(4, minute(0)).unwrap();
+        counter.add(5, minute(2)).unwrap();
+
+        assert!(counter.add(-1, minute(2)).is_none());
+        assert_eq!(counter.sum(minute(2), 1), 5);
+        assert_eq!(counter.sum(minute(2), 3), 12);
+        assert_eq!(counter.sum(minute(1), 2), 7);
+    }
+
+    #[test]
+    fn prune_windowed_counter() {
+        let mut counter = WindowedCounter::new("h1", Duration::minutes(1), 3);
+
+        counter.add(1, minute(0)).unwrap();
+        counter.add(2, minute(1)).unwrap();
+        counter.add(3, minute(3)).unwrap();
+
+        assert_eq!(counter.value(), 5);
+
+        counter.expire(minute(5));
+
+        assert_eq!(counter.value(), 3);
+    }
+
+    #[test]
+    fn apply_windowed_counter_increments() {
+        let mut c1 = WindowedCounter::new("h1", Duration::minutes(1), 5);
+        let mut c2 = WindowedCounter::new("h2", Duration::minutes(1), 5);
+
+        let op1 = c1.add(5, minute(1)).unwrap();
+        let op2 = c2.add(7, minute(1)).unwrap();
+        let op3 = c2.add(2, minute(2)).unwrap();
+
+        c1.apply(&op2);
+        c1.apply(&op3);
+        c2.apply(&op1);
+
+        assert_eq!(c1.sum(minute(2), 2), 14);
+        assert_eq!(c2.sum(minute(2), 2), 14);
+    }
+
+    #[test]
+    fn merge_windowed_counter_with_clock_skew() {
+        let mut c1 = WindowedCounter::new("h1", Duration::minutes(1), 3);
+        let mut c2 = WindowedCounter::new("h2", Duration::minutes(1), 3);
+
+        c1.add(1, minute(0)).unwrap();
+        c1.add(2, minute(2)).unwrap();
+        c2.add(3, minute(1)).unwrap();
+        c2.add(4, minute(3)).unwrap();
+
+        c1.merge(&c2);
+        c2.merge(&c1);
+
+        assert_eq!(c1.value(), 10);
+        assert_eq!(c2.value(), 10);
+        assert_eq!(c1.sum(minute(3), 2), 6);
+        assert_eq!(c2.sum(minute(3), 2), 6);
+    }
+
+    #[test]
+    fn merge_windowed_counter_from_future() {
+        let mut c1 = WindowedCounter::new("h1", Duration::minutes(1), 3);
+        let mut c2 = WindowedCounter::new("h2", Duration::minutes(1), 3);
+
+        c1.add(1, minute(0)).unwrap();
+        c1.add(2, minute(1)).unwrap();
+        c2.add(5, minute(1000)).unwrap();
+
+        c1.merge(&c2);
+        c1.add(3, minute(2)).unwrap();
+
+        assert_eq!(c1.sum(minute(2), 3), 6);
+        assert_eq!(c1.sum(minute(1000), 1), 5);
+    }
 }
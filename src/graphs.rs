@@ -0,0 +1,391 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+use uuid::Uuid;
+
+use core::{StateRDT, OperationRDT, DiffRDT};
+use merkle::{MerkleTree, Digest, item_hash};
+use sets::{ObserveRemoveSet, ORSetOperation};
+
+// Every edge carries the tags of its endpoint vertices that the adding
+// replica had observed. Removing a vertex tombstones those tags, which also
+// removes edges added concurrently, and re-adding the vertex creates a new
+// tag that does not bring them back.
+type Edge<V, E> = (V, V, E, BTreeSet<Uuid>);
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct ORGraph<V: Hash + Eq + Clone, E: Hash + Eq + Clone> {
+    vertices: ObserveRemoveSet<V>,
+    edges: ObserveRemoveSet<Edge<V, E>>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct ORGraphOperation<V, E> {
+    vertex_op: Option<ORSetOperation<V>>,
+    edge_ops: Vec<ORSetOperation<Edge<V, E>>>,
+}
+
+impl<V, E> ORGraph<V, E>
+    where V: Hash + Eq + Clone,
+          E: Hash + Eq + Clone
+{
+    pub fn new() -> ORGraph<V, E> {
+        ORGraph {
+            vertices: ObserveRemoveSet::new(),
+            edges: ObserveRemoveSet::new(),
+        }
+    }
+
+    pub fn contains_vertex(&self, vertex: &V) -> bool {
+        self.vertices.contains(vertex)
+    }
+
+    pub fn contains_edge(&self, from: &V, to: &V, label: &E) -> bool {
+        self.live_edges().iter()
+            .any(|&(ref f, ref t, ref l, _)| f == from && t == to && l == label)
+    }
+
+    pub fn vertices(&self) -> HashSet<V> {
+        self.vertices.value()
+    }
+
+    pub fn edges(&self) -> HashSet<(V, V, E)> {
+        self.live_edges()
+            .into_iter()
+            .map(|(from, to, label, _)| (from, to, label))
+            .collect()
+    }
+
+    pub fn out_neighbours(&self, vertex: &V) -> HashSet<V> {
+        self.edges().into_iter()
+            .filter(|&(ref from, _, _)| from == vertex)
+            .map(|(_, to, _)| to)
+            .collect()
+    }
+
+    pub fn in_neighbours(&self, vertex: &V) -> HashSet<V> {
+        self.edges().into_iter()
+            .filter(|&(_, ref to, _)| to == vertex)
+            .map(|(from, _, _)| from)
+            .collect()
+    }
+
+    pub fn out_degree(&self, vertex: &V) -> usize {
+        self.edges().iter().filter(|&&(ref from, _, _)| from == vertex).count()
+    }
+
+    pub fn in_degree(&self, vertex: &V) -> usize {
+        self.edges().iter().filter(|&&(_, ref to, _)| to == vertex).count()
+    }
+
+    pub fn reachable(&self, from: &V, to: &V) -> bool {
+        if !self.contains_vertex(from) || !self.contains_vertex(to) {
+            return false;
+        }
+
+        let mut adjacency: HashMap<V, Vec<V>> = HashMap::new();
+
+        for (edge_from, edge_to, _) in self.edges() {
+            adjacency.entry(edge_from).or_insert(Vec::new()).push(edge_to);
+        }
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+
+        visited.insert(from.clone());
+        queue.push_back(from.clone());
+
+        while let Some(vertex) = queue.pop_front() {
+            if vertex == *to {
+                return true;
+            }
+
+            for next in adjacency.get(&vertex).into_iter().flat_map(|n| n.iter()) {
+                if visited.insert(next.clone()) {
+                    queue.push_back(next.clone());
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn add_vertex(&mut self, vertex: V) -> ORGraphOperation<V, E> {
+        ORGraphOperation {
+            vertex_op: Some(self.vertices.add(vertex)),
+            edge_ops: Vec::new(),
+        }
+    }
+
+    pub fn remove_vertex(&mut self, vertex: V) -> Option<ORGraphOperation<V, E>> {
+        if !self.contains_vertex(&vertex) {
+            return None;
+        }
+
+        let incident = self.edges.value()
+            .into_iter()
+            .filter(|&(ref from, ref to, _, _)| *from == vertex || *to == vertex)
+            .collect::<Vec<_>>();
+
+        let edge_ops = incident.into_iter()
+            .filter_map(|edge| self.edges.remove(edge))
+            .collect();
+
+        Some(ORGraphOperation {
+            vertex_op: self.vertices.remove(vertex),
+            edge_ops: edge_ops,
+        })
+    }
+
+    pub fn add_edge(&mut self, from: V, to: V, label: E) -> Option<ORGraphOperation<V, E>> {
+        if !self.contains_vertex(&from) || !self.contains_vertex(&to) {
+            return None;
+        }
+
+        let anchors = self.vertices.tags(&from).into_iter()
+            .chain(self.vertices.tags(&to))
+            .collect();
+
+        Some(ORGraphOperation {
+            vertex_op: None,
+            edge_ops: vec![self.edges.add((from, to, label, anchors))],
+        })
+    }
+
+    pub fn remove_edge(&mut self, from: V, to: V, label: E) -> Option<ORGraphOperation<V, E>> {
+        if !self.contains_edge(&from, &to, &label) {
+            return None;
+        }
+
+        let matching = self.edges.value()
+            .into_iter()
+            .filter(|&(ref f, ref t, ref l, _)| *f == from && *t == to && *l == label)
+            .collect::<Vec<_>>();
+
+        Some(ORGraphOperation {
+            vertex_op: None,
+            edge_ops: matching.into_iter().filter_map(|edge| self.edges.remove(edge)).collect(),
+        })
+    }
+
+    // An edge is live while both endpoints still have one of the tags it
+    // was anchored to. Each vertex's tags are looked up once per call.
+    fn live_edges(&self) -> Vec<Edge<V, E>> {
+        let mut tags: HashMap<V, HashSet<Uuid>> = HashMap::new();
+
+        self.edges.value()
+            .into_iter()
+            .filter(|&(ref from, ref to, _, ref anchors)| {
+                [from, to].iter().all(|&vertex| {
+                    let vertex_tags = tags.entry(vertex.clone())
+                        .or_insert_with(|| self.vertices.tags(vertex));
+
+                    vertex_tags.iter().any(|tag| anchors.contains(tag))
+                })
+            })
+            .collect()
+    }
+}
+
+impl<V, E> OperationRDT for ORGraph<V, E>
+    where V: Hash + Eq + Clone,
+          E: Hash + Eq + Clone
+{
+    type Operation = ORGraphOperation<V, E>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        if let Some(ref vertex_op) = op.vertex_op {
+            self.vertices.apply(vertex_op);
+        }
+
+        for edge_op in &op.edge_ops {
+            self.edges.apply(edge_op);
+        }
+    }
+}
+
+impl<V, E> StateRDT for ORGraph<V, E>
+    where V: Hash + Eq + Clone,
+          E: Hash + Eq + Clone
+{
+    fn merge(&mut self, other: &Self) {
+        self.vertices.merge(&other.vertices);
+        self.edges.merge(&other.edges);
+    }
+}
+
+impl<V, E> DiffRDT for ORGraph<V, E>
+    where V: Hash + Eq + Clone,
+          E: Hash + Eq + Clone
+{
+    type Summary = (MerkleTree, MerkleTree);
+    type Delta = ORGraph<V, E>;
+
+    fn summary(&self) -> Self::Summary {
+        (self.vertices.summary(), self.edges.summary())
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        ORGraph {
+            vertices: self.vertices.diff(&summary.0),
+            edges: self.edges.diff(&summary.1),
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
+    }
+}
+
+impl<V, E> Digest for ORGraph<V, E>
+    where V: Hash + Eq + Clone,
+          E: Hash + Eq + Clone
+{
+    fn digest(&self) -> u64 {
+        item_hash(&(self.vertices.digest(), self.edges.digest()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ORGraph;
+
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    #[test]
+    fn make_or_graph() {
+        let graph: ORGraph<&str, ()> = ORGraph::new();
+
+        assert!(graph.vertices().is_empty());
+        assert!(graph.edges().is_empty());
+    }
+
+    #[test]
+    fn add_or_graph_edges() {
+        let mut graph = ORGraph::new();
+
+        graph.add_vertex("a");
+        graph.add_vertex("b");
+        graph.add_vertex("c");
+        graph.add_edge("a", "b", ()).unwrap();
+        graph.add_edge("b", "c", ()).unwrap();
+
+        assert!(graph.add_edge("a", "d", ()).is_none());
+        assert_eq!(graph.out_neighbours(&"a"), HashSet::from_iter(vec!["b"]));
+        assert_eq!(graph.in_neighbours(&"c"), HashSet::from_iter(vec!["b"]));
+        assert_eq!(graph.out_degree(&"b"), 1);
+        assert_eq!(graph.in_degree(&"a"), 0);
+        assert!(graph.reachable(&"a", &"c"));
+        assert!(!graph.reachable(&"c", &"a"));
+    }
+
+    #[test]
+    fn stage_or_graph_changes_on_a_clone() {
+        let mut graph = ORGraph::new();
+
+        graph.add_vertex("a");
+        graph.add_vertex("b");
+
+        let mut staged = graph.clone();
+        let op = staged.add_edge("a", "b", ()).unwrap();
+
+        assert!(!graph.reachable(&"a", &"b"));
+
+        graph.apply(&op);
+
+        assert!(graph.reachable(&"a", &"b"));
+    }
+
+    #[test]
+    fn remove_or_graph_vertex_removes_edges() {
+        let mut graph = ORGraph::new();
+
+        graph.add_vertex("a");
+        graph.add_vertex("b");
+        graph.add_edge("a", "b", "depends").unwrap();
+        graph.remove_vertex("b").unwrap();
+        graph.add_vertex("b");
+
+        assert!(graph.edges().is_empty());
+        assert!(graph.remove_edge("a", "b", "depends").is_none());
+    }
+
+    #[test]
+    fn apply_edge_with_concurrently_removed_vertex() {
+        let mut g1 = ORGraph::new();
+        let mut g2 = ORGraph::new();
+
+        let op1 = g1.add_vertex("a");
+        let op2 = g1.add_vertex("b");
+        g2.apply(&op1);
+        g2.apply(&op2);
+
+        let op3 = g1.add_edge("a", "b", ()).unwrap();
+        let op4 = g2.remove_vertex("b").unwrap();
+
+        g1.apply(&op4);
+        g2.apply(&op3);
+
+        assert_eq!(g1.edges(), HashSet::new());
+        assert_eq!(g2.edges(), HashSet::new());
+        assert_eq!(g1.vertices(), HashSet::from_iter(vec!["a"]));
+
+        let op5 = g2.add_vertex("b");
+        g1.apply(&op5);
+
+        assert_eq!(g1.edges(), HashSet::new());
+        assert_eq!(g2.edges(), HashSet::new());
+
+        let op6 = g1.add_edge("a", "b", ()).unwrap();
+        g2.apply(&op6);
+
+        assert_eq!(g2.edges(), HashSet::from_iter(vec![("a", "b", ())]));
+    }
+
+    #[test]
+    fn merge_or_graph_state() {
+        let mut g1 = ORGraph::new();
+        let mut g2 = ORGraph::new();
+
+        g1.add_vertex(1);
+        g1.add_vertex(2);
+        g2.merge(&g1);
+
+        g1.add_edge(1, 2, ()).unwrap();
+        g2.add_vertex(3);
+        g2.add_edge(2, 3, ()).unwrap();
+
+        g1.merge(&g2);
+        g2.merge(&g1);
+
+        assert!(g1.reachable(&1, &3));
+        assert!(g2.reachable(&1, &3));
+        assert_eq!(g1.edges(), g2.edges());
+    }
+
+    #[test]
+    fn diff_or_graph_state() {
+        let mut g1 = ORGraph::new();
+        let mut g2 = ORGraph::new();
+
+        g1.add_vertex(1);
+        g1.add_vertex(2);
+        g2.merge(&g1);
+
+        g2.add_vertex(3);
+        g2.add_edge(2, 3, ()).unwrap();
+
+        let delta = g2.diff(&g1.summary());
+
+        assert!(delta.vertices().contains(&3));
+        assert!(!delta.vertices().contains(&1));
+
+        g1.apply_diff(&delta);
+
+        assert!(g1.reachable(&2, &3));
+        assert_eq!(g1.edges(), g2.edges());
+    }
+}
@@ -0,0 +1,62 @@
+use std::hash::{Hash, Hasher};
+
+use siphasher::sip::SipHasher;
+
+// Values hashed here are compared between replicas, so the hash has to be
+// the same on every build: SipHash-2-4 with fixed keys, fed integers as
+// little-endian bytes of a fixed width.
+pub struct StableHasher(SipHasher);
+
+impl StableHasher {
+    pub fn new() -> StableHasher {
+        StableHasher(SipHasher::new_with_keys(0, 0))
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.0.write(&[i]);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.0.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.0.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+pub fn stable_hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = StableHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::stable_hash;
+
+    #[test]
+    fn stable_hash_values() {
+        assert_eq!(stable_hash(&1u64), stable_hash(&1usize));
+        assert_eq!(stable_hash(&"alice"), stable_hash(&"alice".to_owned()));
+        assert_eq!(stable_hash(&(1u32, "a")), 15273658027882655211);
+    }
+}
@@ -0,0 +1,392 @@
+use std::cmp;
+use std::hash::Hash;
+
+use core::{StateRDT, OperationRDT, DiffRDT};
+use hashing::stable_hash;
+use merkle::{Digest, item_hash};
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct HyperLogLogOperation {
+    precision: u8,
+    index: usize,
+    rank: u8,
+}
+
+impl HyperLogLog {
+    pub fn new(precision: u8) -> HyperLogLog {
+        assert!(precision >= 4 && precision <= 16, "precision must be between 4 and 16");
+
+        HyperLogLog {
+            precision: precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn insert<T: Hash>(&mut self, value: &T) -> Option<HyperLogLogOperation> {
+        let hash = stable_hash(value);
+
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+
+        if self.registers[index] >= rank {
+            return None;
+        }
+
+        let op = HyperLogLogOperation {
+            precision: self.precision,
+            index: index,
+            rank: rank,
+        };
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+
+        let sum = self.registers.iter()
+            .map(|&r| 2f64.powi(-(r as i32)))
+            .fold(0.0, |acc, x| acc + x);
+
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+
+        // Small cardinalities are better estimated by linear counting over
+        // the empty registers.
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        }
+        else {
+            raw
+        };
+
+        estimate.round() as u64
+    }
+
+    // Folds the registers into a coarser sketch.
+    fn downsample(&self, precision: u8) -> HyperLogLog {
+        let shift = self.precision - precision;
+        let mut coarse = HyperLogLog::new(precision);
+
+        for (index, &rank) in self.registers.iter().enumerate() {
+            if rank == 0 {
+                continue;
+            }
+
+            let (index, rank) = downsample_register(index, rank, shift);
+
+            let register = &mut coarse.registers[index];
+            *register = cmp::max(*register, rank);
+        }
+
+        coarse
+    }
+
+    // Sketches and operations come from peers, so their precision, indices
+    // and ranks are all checked before use.
+    fn is_valid(&self) -> bool {
+        valid_precision(self.precision) &&
+            self.registers.len() == 1 << self.precision &&
+            self.registers.iter().all(|&rank| rank <= max_rank(self.precision))
+    }
+}
+
+fn valid_precision(precision: u8) -> bool {
+    precision >= 4 && precision <= 16
+}
+
+// The rest of the hash has 64 - precision bits and a guard bit set below
+// them, so the rank can't exceed one more than that.
+fn max_rank(precision: u8) -> u8 {
+    64 - precision + 1
+}
+
+// The index bits that are dropped become the leading bits of the rest of
+// the hash.
+fn downsample_register(index: usize, rank: u8, shift: u8) -> (usize, u8) {
+    let dropped = (index & ((1 << shift) - 1)) as u32;
+    let rank = if dropped == 0 {
+        shift + rank
+    }
+    else {
+        (dropped.leading_zeros() - (32 - shift as u32)) as u8 + 1
+    };
+
+    (index >> shift, rank)
+}
+
+impl OperationRDT for HyperLogLog {
+    type Operation = HyperLogLogOperation;
+
+    // Operations from a finer sketch are folded into our registers; one
+    // from a coarser sketch downsamples this one first, as merging does.
+    // Malformed operations are ignored.
+    fn apply(&mut self, op: &Self::Operation) {
+        let valid = valid_precision(op.precision) &&
+            op.index < 1 << op.precision &&
+            op.rank <= max_rank(op.precision);
+
+        if !valid {
+            return;
+        }
+
+        if op.precision < self.precision {
+            *self = self.downsample(op.precision);
+        }
+
+        let (index, rank) = if op.rank == 0 {
+            (op.index >> (op.precision - self.precision), 0)
+        }
+        else {
+            downsample_register(op.index, op.rank, op.precision - self.precision)
+        };
+
+        let register = &mut self.registers[index];
+        *register = cmp::max(*register, rank);
+    }
+}
+
+impl StateRDT for HyperLogLog {
+    // Sketches of different precision merge at the coarser one; malformed
+    // states are ignored.
+    fn merge(&mut self, other: &Self) {
+        if !other.is_valid() {
+            return;
+        }
+
+        if other.precision > self.precision {
+            return self.merge(&other.downsample(self.precision));
+        }
+
+        if other.precision < self.precision {
+            *self = self.downsample(other.precision);
+        }
+
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = cmp::max(*register, *other);
+        }
+    }
+}
+
+// The registers are the whole state, but they are small and a peer only
+// needs the ones where it lags behind. The delta is a sketch holding just
+// those registers; a peer of another precision gets the whole sketch, which
+// merging then downsamples.
+impl DiffRDT for HyperLogLog {
+    type Summary = Vec<u8>;
+    type Delta = HyperLogLog;
+
+    fn summary(&self) -> Self::Summary {
+        self.registers.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if summary.len() != self.registers.len() {
+            return self.clone();
+        }
+
+        let registers = self.registers.iter()
+            .zip(summary.iter())
+            .map(|(&rank, &theirs)| if rank > theirs { rank } else { 0 })
+            .collect();
+
+        HyperLogLog { precision: self.precision, registers: registers }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
+    }
+}
+
+impl Digest for HyperLogLog {
+    fn digest(&self) -> u64 {
+        item_hash(&(self.precision, &self.registers))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HyperLogLog, HyperLogLogOperation};
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    fn within(estimate: u64, actual: u64, error: f64) -> bool {
+        (estimate as f64 - actual as f64).abs() <= actual as f64 * error
+    }
+
+    #[test]
+    fn make_hyper_log_log() {
+        let hll = HyperLogLog::new(12);
+
+        assert_eq!(hll.precision(), 12);
+        assert_eq!(hll.estimate(), 0);
+    }
+
+    #[test]
+    fn insert_hyper_log_log() {
+        let mut hll = HyperLogLog::new(12);
+
+        for i in 0..10000 {
+            hll.insert(&i);
+        }
+
+        assert!(hll.insert(&0).is_none());
+        assert!(within(hll.estimate(), 10000, 0.05));
+    }
+
+    #[test]
+    fn apply_hyper_log_log_ops_of_different_precision() {
+        let mut h1 = HyperLogLog::new(12);
+        let mut h2 = HyperLogLog::new(4);
+        let mut h3 = HyperLogLog::new(4);
+
+        h2.apply(&h1.insert(&"alice").unwrap());
+        h3.insert(&"alice");
+
+        assert_eq!(h2.registers, h3.registers);
+
+        h1.apply(&h3.insert(&"bob").unwrap());
+        h2.insert(&"bob");
+
+        assert_eq!(h1.precision(), 4);
+        assert_eq!(h1.registers, h2.registers);
+    }
+
+    #[test]
+    fn ignore_malformed_hyper_log_log_input() {
+        let mut h1 = HyperLogLog::new(12);
+        let mut h2 = HyperLogLog::new(12);
+
+        h1.insert(&"alice");
+        h2.insert(&"alice");
+
+        h1.apply(&HyperLogLogOperation { precision: 12, index: 1 << 12, rank: 1 });
+        h1.apply(&HyperLogLogOperation { precision: 12, index: 0, rank: 255 });
+        h1.apply(&HyperLogLogOperation { precision: 20, index: 0, rank: 1 });
+
+        let mut hostile = HyperLogLog::new(14);
+        hostile.registers[0] = 255;
+        h1.merge(&hostile);
+
+        assert_eq!(h1.registers, h2.registers);
+    }
+
+    #[test]
+    fn merge_hyper_log_logs_of_different_precision() {
+        let mut h1 = HyperLogLog::new(12);
+        let mut h2 = HyperLogLog::new(10);
+
+        for i in 0..5000 {
+            h1.insert(&i);
+            h2.insert(&i);
+        }
+
+        let mut h3 = h2.clone();
+        h3.merge(&h1);
+        h1.merge(&HyperLogLog::new(10));
+
+        assert_eq!(h1.precision(), 10);
+        assert_eq!(h1.registers, h2.registers);
+        assert_eq!(h3.registers, h2.registers);
+    }
+
+    #[test]
+    fn insert_few_hyper_log_log() {
+        let mut hll = HyperLogLog::new(12);
+
+        for user in &["alice", "bob", "carol", "alice"] {
+            hll.insert(user);
+        }
+
+        assert_eq!(hll.estimate(), 3);
+    }
+
+    #[test]
+    fn apply_hyper_log_log_ops() {
+        let mut h1 = HyperLogLog::new(10);
+        let mut h2 = HyperLogLog::new(10);
+
+        for i in 0..1000 {
+            if let Some(op) = h1.insert(&i) {
+                h2.apply(&op);
+            }
+        }
+
+        assert_eq!(h1.estimate(), h2.estimate());
+    }
+
+    #[test]
+    fn merge_hyper_log_log_state() {
+        let mut h1 = HyperLogLog::new(12);
+        let mut h2 = HyperLogLog::new(12);
+
+        for i in 0..6000 {
+            h1.insert(&i);
+        }
+
+        for i in 4000..10000 {
+            h2.insert(&i);
+        }
+
+        h1.merge(&h2);
+        h2.merge(&h1);
+
+        assert_eq!(h1.estimate(), h2.estimate());
+        assert!(within(h1.estimate(), 10000, 0.05));
+    }
+
+    #[test]
+    fn diff_hyper_log_log_state() {
+        let mut h1 = HyperLogLog::new(12);
+        let mut h2 = HyperLogLog::new(12);
+
+        for i in 0..1000 {
+            h1.insert(&i);
+        }
+
+        h2.merge(&h1);
+        h2.insert(&1000);
+
+        assert_eq!(h2.diff(&h2.summary()).estimate(), 0);
+
+        h1.apply_diff(&h2.diff(&h1.summary()));
+
+        assert_eq!(h1.estimate(), h2.estimate());
+    }
+
+    #[test]
+    fn diff_hyper_log_logs_of_different_precision() {
+        let mut h1 = HyperLogLog::new(12);
+        let mut h2 = HyperLogLog::new(10);
+
+        for i in 0..1000 {
+            h1.insert(&i);
+        }
+
+        h2.apply_diff(&h1.diff(&h2.summary()));
+        h1.apply_diff(&h2.diff(&h1.summary()));
+
+        assert_eq!(h1.precision(), 10);
+        assert_eq!(h1.estimate(), h2.estimate());
+    }
+}
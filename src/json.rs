@@ -0,0 +1,906 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use chrono::{DateTime, UTC};
+use rustc_serialize::{Decodable, Decoder};
+use serde_json::{self, Value};
+
+use clock::LamportTimestamp;
+use core::{StateRDT, OperationRDT, DiffRDT, ValueFactory};
+use counters::{PNCounter, SetPNCounterOperation};
+use lists::{RGAList, RGAListOperation};
+use maps::{ObserveRemoveMap, ORMapOperation};
+use merkle::{MerkleTree, Digest, item_hash, in_buckets};
+use registers::{LWWRegister, SetLWWRegisterOperation};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum JsonScalar {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, RustcEncodable, RustcDecodable)]
+pub enum JsonKind {
+    Scalar,
+    Map,
+    List,
+    Counter,
+    Text,
+}
+
+#[derive(Debug, Clone, RustcEncodable)]
+pub struct JsonNode {
+    replica: String,
+    kind: LWWRegister<JsonKind>,
+    scalar: LWWRegister<JsonScalar>,
+    map: ObserveRemoveMap<String, JsonNode>,
+    list: RGAList<()>,
+    items: HashMap<LamportTimestamp, JsonNode>,
+    counter: PNCounter<String, i64>,
+    text: RGAList<char>,
+}
+
+// Maps are encoded without their context, so a decoded node hands its
+// replica back to its map.
+#[derive(RustcDecodable)]
+struct JsonNodeState {
+    replica: String,
+    kind: LWWRegister<JsonKind>,
+    scalar: LWWRegister<JsonScalar>,
+    map: ObserveRemoveMap<String, JsonNode>,
+    list: RGAList<()>,
+    items: HashMap<LamportTimestamp, JsonNode>,
+    counter: PNCounter<String, i64>,
+    text: RGAList<char>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum JsonOperation {
+    Kind(SetLWWRegisterOperation<JsonKind>),
+    Scalar(SetLWWRegisterOperation<JsonScalar>),
+    Map(Box<ORMapOperation<String, JsonNode>>),
+    List(RGAListOperation<()>),
+    ListItem(LamportTimestamp, Box<JsonOperation>),
+    Counter(SetPNCounterOperation<String, i64>),
+    Text(RGAListOperation<char>),
+    Multiple(Vec<JsonOperation>),
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct JsonDoc {
+    root: JsonNode,
+}
+
+// Each part of a node is summarized by its own DiffRDT impl. List items are
+// folded into a tree as (id, digest) items, the way map values are.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct JsonSummary {
+    kind: DateTime<UTC>,
+    scalar: DateTime<UTC>,
+    map: MerkleTree,
+    list: MerkleTree,
+    items: MerkleTree,
+    counter: HashMap<String, (i64, i64)>,
+    text: MerkleTree,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct JsonDelta {
+    kind: Option<SetLWWRegisterOperation<JsonKind>>,
+    scalar: Option<SetLWWRegisterOperation<JsonScalar>>,
+    map: ObserveRemoveMap<String, JsonNode>,
+    list: Vec<RGAListOperation<()>>,
+    items: HashMap<LamportTimestamp, JsonNode>,
+    counter: Vec<SetPNCounterOperation<String, i64>>,
+    text: Vec<RGAListOperation<char>>,
+}
+
+impl<'a> From<&'a str> for PathSegment {
+    fn from(key: &'a str) -> PathSegment {
+        PathSegment::Key(key.to_owned())
+    }
+}
+
+impl From<String> for PathSegment {
+    fn from(key: String) -> PathSegment {
+        PathSegment::Key(key)
+    }
+}
+
+impl From<usize> for PathSegment {
+    fn from(index: usize) -> PathSegment {
+        PathSegment::Index(index)
+    }
+}
+
+impl Default for JsonScalar {
+    fn default() -> JsonScalar {
+        JsonScalar::Null
+    }
+}
+
+// Floats have no Hash of their own; hashing their bits is enough to tell
+// replicas apart.
+impl Hash for JsonScalar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            JsonScalar::Null => 0.hash(state),
+            JsonScalar::Bool(b) => (1, b).hash(state),
+            JsonScalar::Integer(i) => (2, i).hash(state),
+            JsonScalar::Float(f) => (3, f.to_bits()).hash(state),
+            JsonScalar::String(ref s) => (4, s).hash(state),
+        }
+    }
+}
+
+impl Default for JsonKind {
+    fn default() -> JsonKind {
+        JsonKind::Scalar
+    }
+}
+
+impl JsonOperation {
+    fn from_vec(mut ops: Vec<JsonOperation>) -> Option<JsonOperation> {
+        match ops.len() {
+            0 => None,
+            1 => ops.pop(),
+            _ => Some(JsonOperation::Multiple(ops)),
+        }
+    }
+}
+
+impl JsonNode {
+    fn new(replica: String) -> JsonNode {
+        JsonNode {
+            replica: replica.clone(),
+            kind: LWWRegister::new(),
+            scalar: LWWRegister::new(),
+            map: ObserveRemoveMap::new(replica.clone()),
+            list: RGAList::new(),
+            items: HashMap::new(),
+            counter: PNCounter::new(replica),
+            text: RGAList::new(),
+        }
+    }
+
+    // Changing the kind drops the data kept for the old one, so none of it
+    // comes back when the node later returns to that kind.
+    fn set_kind(&mut self, kind: JsonKind) -> Vec<JsonOperation> {
+        if *self.kind.value() == kind {
+            return Vec::new();
+        }
+
+        let mut ops = vec![JsonOperation::Kind(self.kind.set(kind))];
+
+        for key in self.map.keys() {
+            ops.extend(self.map.remove(key).map(|op| JsonOperation::Map(Box::new(op))));
+        }
+
+        while let Some(op) = self.list.remove(0) {
+            ops.push(JsonOperation::List(op));
+        }
+
+        while let Some(op) = self.text.remove(0) {
+            ops.push(JsonOperation::Text(op));
+        }
+
+        let count = self.counter.value();
+
+        if count != 0 {
+            ops.extend(self.counter.add(-count).map(JsonOperation::Counter));
+        }
+
+        ops
+    }
+
+    fn child(&self, segment: &PathSegment) -> Option<&JsonNode> {
+        match (segment, *self.kind.value()) {
+            (&PathSegment::Key(ref key), JsonKind::Map) => self.map.get(key),
+            (&PathSegment::Index(index), JsonKind::List) => {
+                self.list.id_at(index).and_then(|id| self.items.get(&id))
+            },
+            _ => None,
+        }
+    }
+
+    fn items_merkle(&self) -> MerkleTree {
+        self.items.iter().map(|(id, item)| item_hash(&(id, item.digest()))).collect()
+    }
+
+    fn item_mut(&mut self, id: &LamportTimestamp) -> &mut JsonNode {
+        let replica = &self.replica;
+
+        self.items.entry(id.clone()).or_insert_with(|| JsonNode::new(replica.clone()))
+    }
+
+    // Missing keys along a path are created on update, but list indices
+    // have to exist already.
+    fn can_update(&self, path: &[PathSegment]) -> bool {
+        let (segment, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return true,
+        };
+
+        // A key under a node of another kind turns it into an empty map.
+        let child = match *segment {
+            PathSegment::Key(ref key) => {
+                if *self.kind.value() != JsonKind::Map {
+                    None
+                }
+                else {
+                    self.map.get(key)
+                }
+            },
+            PathSegment::Index(index) => {
+                if *self.kind.value() != JsonKind::List {
+                    return false;
+                }
+
+                match self.list.id_at(index) {
+                    Some(id) => self.items.get(&id),
+                    None => return false,
+                }
+            },
+        };
+
+        match child {
+            Some(child) => child.can_update(rest),
+            None => rest.iter().all(|s| match *s {
+                PathSegment::Key(_) => true,
+                PathSegment::Index(_) => false,
+            }),
+        }
+    }
+
+    fn update_path<F>(&mut self, path: &[PathSegment], update_fn: F) -> Option<JsonOperation>
+        where F: FnOnce(&mut JsonNode) -> Vec<JsonOperation>
+    {
+        let (segment, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return JsonOperation::from_vec(update_fn(self)),
+        };
+
+        match *segment {
+            PathSegment::Key(ref key) => {
+                if !self.can_update(path) {
+                    return None;
+                }
+
+                let mut ops = Vec::new();
+
+                ops.extend(self.set_kind(JsonKind::Map));
+                ops.extend(self.map.update(key.clone(), |child| child.update_path(rest, update_fn))
+                           .map(|op| JsonOperation::Map(Box::new(op))));
+
+                JsonOperation::from_vec(ops)
+            },
+            PathSegment::Index(index) => {
+                if *self.kind.value() != JsonKind::List {
+                    return None;
+                }
+
+                let id = match self.list.id_at(index) {
+                    Some(id) => id,
+                    None => return None,
+                };
+
+                self.item_mut(&id).update_path(rest, update_fn)
+                    .map(|op| JsonOperation::ListItem(id, Box::new(op)))
+            },
+        }
+    }
+
+    fn assign(&mut self, value: &Value) -> Vec<JsonOperation> {
+        let mut ops = Vec::new();
+
+        match *value {
+            Value::Object(ref object) => {
+                ops.extend(self.set_kind(JsonKind::Map));
+
+                for key in self.map.keys() {
+                    if !object.contains_key(&key) {
+                        ops.extend(self.map.remove(key).map(|op| JsonOperation::Map(Box::new(op))));
+                    }
+                }
+
+                for (key, child_value) in object {
+                    let op = self.map.update(key.clone(), |child| {
+                        JsonOperation::from_vec(child.assign(child_value))
+                    });
+
+                    ops.extend(op.map(|op| JsonOperation::Map(Box::new(op))));
+                }
+            },
+            Value::Array(ref array) => {
+                ops.extend(self.set_kind(JsonKind::List));
+
+                while let Some(op) = self.list.remove(0) {
+                    ops.push(JsonOperation::List(op));
+                }
+
+                for child_value in array {
+                    let index = self.list.len();
+
+                    ops.extend(self.insert_item(index, child_value));
+                }
+            },
+            ref scalar => {
+                ops.extend(self.set_kind(JsonKind::Scalar));
+                ops.push(JsonOperation::Scalar(self.scalar.set(scalar_from_json(scalar))));
+            },
+        }
+
+        ops
+    }
+
+    fn insert_item(&mut self, index: usize, value: &Value) -> Vec<JsonOperation> {
+        let mut ops = Vec::new();
+
+        ops.extend(self.set_kind(JsonKind::List));
+
+        let op = match self.list.insert(index, ()) {
+            Some(op) => op,
+            None => return ops,
+        };
+        let id = self.list.id_at(index).unwrap();
+
+        ops.push(JsonOperation::List(op));
+        ops.extend(JsonOperation::from_vec(self.item_mut(&id).assign(value))
+                   .map(|op| JsonOperation::ListItem(id, Box::new(op))));
+
+        ops
+    }
+
+    fn remove_child(&mut self, segment: &PathSegment) -> Vec<JsonOperation> {
+        let op = match (segment, *self.kind.value()) {
+            (&PathSegment::Key(ref key), JsonKind::Map) => {
+                self.map.remove(key.clone()).map(|op| JsonOperation::Map(Box::new(op)))
+            },
+            (&PathSegment::Index(index), JsonKind::List) => {
+                self.list.remove(index).map(JsonOperation::List)
+            },
+            _ => None,
+        };
+
+        op.into_iter().collect()
+    }
+
+    fn to_json(&self) -> Value {
+        match *self.kind.value() {
+            JsonKind::Scalar => scalar_to_json(self.scalar.value()),
+            JsonKind::Map => {
+                let mut object = serde_json::Map::new();
+
+                for key in self.map.keys() {
+                    let child = self.map.get(&key).map(|c| c.to_json()).unwrap_or(Value::Null);
+
+                    object.insert(key, child);
+                }
+
+                Value::Object(object)
+            },
+            JsonKind::List => {
+                Value::Array(self.list.iter()
+                             .map(|(id, _)| self.items.get(id)
+                                  .map(|c| c.to_json())
+                                  .unwrap_or(Value::Null))
+                             .collect())
+            },
+            JsonKind::Counter => Value::from(self.counter.value()),
+            JsonKind::Text => Value::String(self.text.iter().map(|(_, c)| *c).collect()),
+        }
+    }
+}
+
+impl Decodable for JsonNode {
+    fn decode<D: Decoder>(d: &mut D) -> Result<JsonNode, D::Error> {
+        let state: JsonNodeState = try!(Decodable::decode(d));
+        let mut map = state.map;
+
+        map.set_context(state.replica.clone());
+
+        Ok(JsonNode {
+            replica: state.replica,
+            kind: state.kind,
+            scalar: state.scalar,
+            map: map,
+            list: state.list,
+            items: state.items,
+            counter: state.counter,
+            text: state.text,
+        })
+    }
+}
+
+impl ValueFactory<String> for JsonNode {
+    type Context = String;
+
+    fn create(_: &String, replica: &String) -> Self {
+        JsonNode::new(replica.clone())
+    }
+}
+
+impl OperationRDT for JsonNode {
+    type Operation = JsonOperation;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        use self::JsonOperation::{Kind, Scalar, Map, List, ListItem, Counter, Text, Multiple};
+
+        match op {
+            &Kind(ref op) => self.kind.apply(op),
+            &Scalar(ref op) => self.scalar.apply(op),
+            &Map(ref op) => self.map.apply(op),
+            &List(ref op) => self.list.apply(op),
+            &ListItem(ref id, ref op) => self.item_mut(id).apply(op),
+            &Counter(ref op) => self.counter.apply(op),
+            &Text(ref op) => self.text.apply(op),
+            &Multiple(ref ops) => {
+                for op in ops {
+                    self.apply(op);
+                }
+            },
+        }
+    }
+}
+
+impl StateRDT for JsonNode {
+    fn merge(&mut self, other: &Self) {
+        self.kind.merge(&other.kind);
+        self.scalar.merge(&other.scalar);
+        self.map.merge(&other.map);
+        self.list.merge(&other.list);
+        self.counter.merge(&other.counter);
+        self.text.merge(&other.text);
+
+        for (id, item) in &other.items {
+            self.item_mut(id).merge(item);
+        }
+    }
+}
+
+impl DiffRDT for JsonNode {
+    type Summary = JsonSummary;
+    type Delta = JsonDelta;
+
+    fn summary(&self) -> Self::Summary {
+        JsonSummary {
+            kind: self.kind.summary(),
+            scalar: self.scalar.summary(),
+            map: self.map.summary(),
+            list: self.list.summary(),
+            items: self.items_merkle(),
+            counter: self.counter.summary(),
+            text: self.text.summary(),
+        }
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.items_merkle().diff(&summary.items);
+
+        let items = self.items.iter()
+            .filter(|&(id, item)| in_buckets(&buckets, item_hash(&(id, item.digest()))))
+            .map(|(id, item)| (id.clone(), item.clone()))
+            .collect();
+
+        JsonDelta {
+            kind: self.kind.diff(&summary.kind),
+            scalar: self.scalar.diff(&summary.scalar),
+            map: self.map.diff(&summary.map),
+            list: self.list.diff(&summary.list),
+            items: items,
+            counter: self.counter.diff(&summary.counter),
+            text: self.text.diff(&summary.text),
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.kind.apply_diff(&delta.kind);
+        self.scalar.apply_diff(&delta.scalar);
+        self.map.apply_diff(&delta.map);
+        self.list.apply_diff(&delta.list);
+        self.counter.apply_diff(&delta.counter);
+        self.text.apply_diff(&delta.text);
+
+        for (id, item) in &delta.items {
+            self.item_mut(id).merge(item);
+        }
+    }
+}
+
+impl Digest for JsonNode {
+    fn digest(&self) -> u64 {
+        let items = self.items_merkle().root();
+
+        item_hash(&[
+            self.kind.digest(),
+            self.scalar.digest(),
+            self.map.digest(),
+            self.list.digest(),
+            self.counter.digest(),
+            self.text.digest(),
+            items,
+        ])
+    }
+}
+
+fn scalar_from_json(value: &Value) -> JsonScalar {
+    match *value {
+        Value::Bool(b) => JsonScalar::Bool(b),
+        Value::Number(ref n) => {
+            match n.as_i64() {
+                Some(i) => JsonScalar::Integer(i),
+                None => JsonScalar::Float(n.as_f64().unwrap_or(0.0)),
+            }
+        },
+        Value::String(ref s) => JsonScalar::String(s.clone()),
+        _ => JsonScalar::Null,
+    }
+}
+
+fn scalar_to_json(scalar: &JsonScalar) -> Value {
+    match *scalar {
+        JsonScalar::Null => Value::Null,
+        JsonScalar::Bool(b) => Value::Bool(b),
+        JsonScalar::Integer(i) => Value::from(i),
+        JsonScalar::Float(f) => {
+            serde_json::Number::from_f64(f).map(Value::Number).unwrap_or(Value::Null)
+        },
+        JsonScalar::String(ref s) => Value::String(s.clone()),
+    }
+}
+
+impl JsonDoc {
+    pub fn new(replica: &str) -> JsonDoc {
+        JsonDoc {
+            root: JsonNode::new(replica.to_owned()),
+        }
+    }
+
+    pub fn from_json(replica: &str, value: &Value) -> JsonDoc {
+        let mut doc = JsonDoc::new(replica);
+
+        doc.set(&[], value.clone());
+
+        doc
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.root.to_json()
+    }
+
+    pub fn get(&self, path: &[PathSegment]) -> Option<Value> {
+        let mut node = &self.root;
+
+        for segment in path {
+            node = match node.child(segment) {
+                Some(child) => child,
+                None => return None,
+            };
+        }
+
+        Some(node.to_json())
+    }
+
+    pub fn set<V: Into<Value>>(&mut self, path: &[PathSegment], value: V) -> Option<JsonOperation> {
+        let value = value.into();
+
+        self.root.update_path(path, |node| node.assign(&value))
+    }
+
+    pub fn insert<V: Into<Value>>(&mut self, path: &[PathSegment], index: usize, value: V)
+        -> Option<JsonOperation>
+    {
+        let value = value.into();
+
+        self.root.update_path(path, |node| node.insert_item(index, &value))
+    }
+
+    pub fn remove(&mut self, path: &[PathSegment]) -> Option<JsonOperation> {
+        let (segment, parent) = match path.split_last() {
+            Some(split) => split,
+            None => return None,
+        };
+
+        if self.get(path).is_none() {
+            return None;
+        }
+
+        self.root.update_path(parent, |node| node.remove_child(segment))
+    }
+
+    pub fn increment(&mut self, path: &[PathSegment], delta: i64) -> Option<JsonOperation> {
+        self.root.update_path(path, |node| {
+            let mut ops = Vec::new();
+
+            ops.extend(node.set_kind(JsonKind::Counter));
+            ops.extend(node.counter.add(delta).map(JsonOperation::Counter));
+
+            ops
+        })
+    }
+
+    pub fn splice_text(&mut self, path: &[PathSegment], index: usize, delete: usize, text: &str)
+        -> Option<JsonOperation>
+    {
+        self.root.update_path(path, |node| {
+            let mut ops = Vec::new();
+
+            ops.extend(node.set_kind(JsonKind::Text));
+
+            for _ in 0..delete {
+                ops.extend(node.text.remove(index).map(JsonOperation::Text));
+            }
+
+            for (i, c) in text.chars().enumerate() {
+                ops.extend(node.text.insert(index + i, c).map(JsonOperation::Text));
+            }
+
+            ops
+        })
+    }
+}
+
+impl OperationRDT for JsonDoc {
+    type Operation = JsonOperation;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        self.root.apply(op);
+    }
+}
+
+impl StateRDT for JsonDoc {
+    fn merge(&mut self, other: &Self) {
+        self.root.merge(&other.root);
+    }
+}
+
+// Nested maps and list items are sent whole, but only those under keys and
+// ids that differ.
+impl DiffRDT for JsonDoc {
+    type Summary = JsonSummary;
+    type Delta = JsonDelta;
+
+    fn summary(&self) -> Self::Summary {
+        self.root.summary()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.root.diff(summary)
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.root.apply_diff(delta);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JsonDoc, JsonOperation, PathSegment};
+
+    use rustc_serialize::json;
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    fn path(segments: Vec<PathSegment>) -> Vec<PathSegment> {
+        segments
+    }
+
+    #[test]
+    fn make_json_doc() {
+        let doc = JsonDoc::new("r1");
+
+        assert_eq!(doc.to_json(), json!(null));
+    }
+
+    #[test]
+    fn set_json_doc_path() {
+        let mut doc = JsonDoc::new("r1");
+
+        doc.set(&path(vec!["user".into(), "name".into()]), "alice").unwrap();
+        doc.set(&path(vec!["user".into(), "age".into()]), 30).unwrap();
+
+        assert_eq!(doc.to_json(), json!({"user": {"name": "alice", "age": 30}}));
+        assert_eq!(doc.get(&path(vec!["user".into(), "name".into()])), Some(json!("alice")));
+        assert_eq!(doc.get(&path(vec!["missing".into()])), None);
+    }
+
+    #[test]
+    fn set_json_doc_list_items() {
+        let mut doc = JsonDoc::new("r1");
+
+        doc.set(&path(vec!["users".into()]), json!([{"name": "a"}, {"name": "b"}])).unwrap();
+        doc.set(&path(vec!["users".into(), 1usize.into(), "name".into()]), "x").unwrap();
+        doc.insert(&path(vec!["users".into()]), 0, json!({"name": "c"})).unwrap();
+
+        assert!(doc.set(&path(vec!["users".into(), 5usize.into(), "name".into()]), "y").is_none());
+        assert_eq!(doc.to_json(), json!({"users": [{"name": "c"}, {"name": "a"}, {"name": "x"}]}));
+    }
+
+    #[test]
+    fn remove_json_doc_path() {
+        let mut doc = JsonDoc::from_json("r1", &json!({"a": 1, "b": [1, 2, 3]}));
+
+        doc.remove(&path(vec!["a".into()])).unwrap();
+        doc.remove(&path(vec!["b".into(), 1usize.into()])).unwrap();
+
+        assert_eq!(doc.to_json(), json!({"b": [1, 3]}));
+    }
+
+    #[test]
+    fn decode_json_doc() {
+        let doc = JsonDoc::from_json("r1", &json!({"a": {"b": 1}}));
+        let mut decoded: JsonDoc = json::decode(&json::encode(&doc).unwrap()).unwrap();
+
+        decoded.set(&path(vec!["a".into(), "c".into()]), 2).unwrap();
+
+        let a = decoded.root.map.get(&"a".to_owned()).unwrap();
+
+        assert_eq!(decoded.root.map.context(), "r1");
+        assert_eq!(a.map.context(), "r1");
+        assert_eq!(a.map.get(&"c".to_owned()).unwrap().replica, "r1");
+        assert_eq!(decoded.to_json(), json!({"a": {"b": 1, "c": 2}}));
+    }
+
+    #[test]
+    fn update_missing_json_doc_index() {
+        let mut doc = JsonDoc::from_json("r1", &json!({"a": {"b": [1]}}));
+        let before = doc.summary();
+
+        assert!(doc.set(&path(vec!["a".into(), "b".into(), 5usize.into(), "c".into()]), 1).is_none());
+        assert!(doc.set(&path(vec!["x".into(), 0usize.into()]), 1).is_none());
+        assert!(doc.remove(&path(vec!["y".into(), "z".into()])).is_none());
+
+        assert_eq!(doc.to_json(), json!({"a": {"b": [1]}}));
+        assert_eq!(doc.summary(), before);
+    }
+
+    #[test]
+    fn overwrite_json_doc_object() {
+        let mut d1 = JsonDoc::new("r1");
+        let mut d2 = JsonDoc::new("r2");
+
+        let ops = vec![
+            d1.set(&[], json!({"a": {"b": 1}, "l": {"x": 1}})).unwrap(),
+            d1.set(&path(vec!["a".into()]), 5).unwrap(),
+            d1.set(&path(vec!["a".into(), "c".into()]), 2).unwrap(),
+            d1.set(&path(vec!["l".into()]), json!([1])).unwrap(),
+            d1.set(&path(vec!["l".into(), "y".into()]), 2).unwrap(),
+        ];
+
+        for op in &ops {
+            d2.apply(op);
+        }
+
+        let expected = json!({"a": {"c": 2}, "l": {"y": 2}});
+
+        assert_eq!(d1.to_json(), expected);
+        assert_eq!(d2.to_json(), expected);
+    }
+
+    #[test]
+    fn apply_repeated_json_doc_increments() {
+        let mut d1 = JsonDoc::new("r1");
+        let mut d2 = JsonDoc::new("r2");
+
+        let ops = vec![
+            d1.increment(&path(vec!["hits".into()]), 2).unwrap(),
+            d1.increment(&path(vec!["hits".into()]), 3).unwrap(),
+            d1.increment(&path(vec!["hits".into()]), -1).unwrap(),
+        ];
+
+        for op in &ops {
+            d2.apply(op);
+        }
+
+        assert_eq!(d1.to_json(), json!({"hits": 4}));
+        assert_eq!(d2.to_json(), json!({"hits": 4}));
+    }
+
+    #[test]
+    fn json_doc_round_trip() {
+        let value = json!({
+            "title": "notes",
+            "done": false,
+            "ratio": 0.5,
+            "tags": ["a", "b"],
+            "owner": {"name": "alice", "id": null},
+        });
+
+        let doc = JsonDoc::from_json("r1", &value);
+
+        assert_eq!(doc.to_json(), value);
+    }
+
+    #[test]
+    fn apply_json_doc_ops() {
+        let mut d1 = JsonDoc::new("r1");
+        let mut d2 = JsonDoc::new("r2");
+
+        let op1 = d1.set(&path(vec!["a".into()]), "x").unwrap();
+        let op2 = d2.increment(&path(vec!["hits".into()]), 2).unwrap();
+        let op3 = d2.splice_text(&path(vec!["body".into()]), 0, 0, "hello").unwrap();
+
+        d2.apply(&op1);
+        d1.apply(&op2);
+        d1.apply(&op3);
+
+        let op4 = d1.increment(&path(vec!["hits".into()]), 3).unwrap();
+        let op5 = d1.splice_text(&path(vec!["body".into()]), 0, 1, "j").unwrap();
+
+        d2.apply(&op4);
+        d2.apply(&op5);
+
+        let expected = json!({"a": "x", "hits": 5, "body": "jello"});
+
+        assert_eq!(d1.to_json(), expected);
+        assert_eq!(d2.to_json(), expected);
+    }
+
+    #[test]
+    fn send_encoded_json_doc_ops() {
+        let mut d1 = JsonDoc::new("r1");
+        let mut d2 = JsonDoc::new("r2");
+
+        let op = d1.set(&path(vec!["a".into()]), json!({"b": [1, 2.5]})).unwrap();
+        let op: JsonOperation = json::decode(&json::encode(&op).unwrap()).unwrap();
+
+        d2.apply(&op);
+
+        assert_eq!(d2.to_json(), d1.to_json());
+    }
+
+    #[test]
+    fn merge_json_doc_state() {
+        let mut d1 = JsonDoc::new("r1");
+        let mut d2 = JsonDoc::new("r2");
+
+        d1.set(&path(vec!["list".into()]), json!([1])).unwrap();
+        d2.merge(&d1);
+
+        d1.insert(&path(vec!["list".into()]), 1, 2).unwrap();
+        d2.set(&path(vec!["name".into()]), "d2").unwrap();
+        d2.set(&path(vec!["list".into(), 0usize.into()]), json!({"x": true})).unwrap();
+
+        d1.merge(&d2);
+        d2.merge(&d1);
+
+        let expected = json!({"list": [{"x": true}, 2], "name": "d2"});
+
+        assert_eq!(d1.to_json(), expected);
+        assert_eq!(d2.to_json(), expected);
+    }
+
+    #[test]
+    fn diff_json_doc_state() {
+        let mut d1 = JsonDoc::new("r1");
+        let mut d2 = JsonDoc::new("r2");
+
+        d1.set(&path(vec!["count".into()]), 1.5).unwrap();
+        d1.set(&path(vec!["tags".into()]), json!(["a", "b"])).unwrap();
+        d2.merge(&d1);
+
+        d2.set(&path(vec!["name".into()]), "d2").unwrap();
+
+        let delta = d2.diff(&d1.summary());
+        let mut d3 = JsonDoc::new("r3");
+
+        d3.set(&[], json!({})).unwrap();
+        d3.apply_diff(&delta);
+
+        assert_eq!(d3.to_json(), json!({"name": "d2"}));
+
+        d1.apply_diff(&delta);
+
+        assert_eq!(d1.to_json(), json!({"count": 1.5, "name": "d2", "tags": ["a", "b"]}));
+        assert_eq!(d1.summary(), d2.summary());
+    }
+}
@@ -0,0 +1,589 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map;
+use std::hash::Hash;
+
+use core::{Lattice, Chain, DiffRDT};
+use merkle::{MerkleTree, Digest, item_hash, in_buckets};
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct MaxLattice<T: Ord + Clone>(T);
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct MinLattice<T: Ord + Clone>(T);
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct SetUnion<T: Hash + Eq + Clone>(HashSet<T>);
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct MapLattice<K: Hash + Eq + Clone, L: Lattice + Clone>(HashMap<K, L>);
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Pair<A: Lattice, B: Lattice>(A, B);
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Lexicographic<A: Chain + Clone, B: Lattice + Clone>(A, B);
+
+fn combine(a: Option<Ordering>, b: Option<Ordering>) -> Option<Ordering> {
+    match (a, b) {
+        (Some(Ordering::Equal), b) => b,
+        (a, Some(Ordering::Equal)) => a,
+        (Some(a), Some(b)) if a == b => Some(a),
+        _ => None,
+    }
+}
+
+impl<T: Ord + Clone> MaxLattice<T> {
+    pub fn new(value: T) -> MaxLattice<T> {
+        MaxLattice(value)
+    }
+
+    pub fn value<'a>(&'a self) -> &'a T {
+        &self.0
+    }
+}
+
+impl<T: Ord + Clone> Lattice for MaxLattice<T> {
+    fn join(&mut self, other: &Self) {
+        if other.0 > self.0 {
+            self.0 = other.0.clone();
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        Some(self.0.cmp(&other.0))
+    }
+}
+
+impl<T: Ord + Clone> Chain for MaxLattice<T> {}
+
+impl<T: Ord + Clone> MinLattice<T> {
+    pub fn new(value: T) -> MinLattice<T> {
+        MinLattice(value)
+    }
+
+    pub fn value<'a>(&'a self) -> &'a T {
+        &self.0
+    }
+}
+
+impl<T: Ord + Clone> Lattice for MinLattice<T> {
+    fn join(&mut self, other: &Self) {
+        if other.0 < self.0 {
+            self.0 = other.0.clone();
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        Some(other.0.cmp(&self.0))
+    }
+}
+
+impl<T: Ord + Clone> Chain for MinLattice<T> {}
+
+impl<T: Hash + Eq + Clone> SetUnion<T> {
+    pub fn new() -> SetUnion<T> {
+        SetUnion(HashSet::new())
+    }
+
+    pub fn value<'a>(&'a self) -> &'a HashSet<T> {
+        &self.0
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.0.contains(value)
+    }
+
+    pub fn insert(&mut self, value: T) -> bool {
+        self.0.insert(value)
+    }
+}
+
+impl<T: Hash + Eq + Clone> Lattice for SetUnion<T> {
+    fn join(&mut self, other: &Self) {
+        for value in &other.0 {
+            if !self.0.contains(value) {
+                self.0.insert(value.clone());
+            }
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        let subset = self.0.is_subset(&other.0);
+        let superset = self.0.is_superset(&other.0);
+
+        match (subset, superset) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (false, false) => None,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, L: Lattice + Clone> MapLattice<K, L> {
+    pub fn new() -> MapLattice<K, L> {
+        MapLattice(HashMap::new())
+    }
+
+    pub fn get(&self, key: &K) -> Option<&L> {
+        self.0.get(key)
+    }
+
+    pub fn iter<'a>(&'a self) -> hash_map::Iter<'a, K, L> {
+        self.0.iter()
+    }
+
+    pub fn values<'a>(&'a self) -> hash_map::Values<'a, K, L> {
+        self.0.values()
+    }
+
+    pub fn insert(&mut self, key: K, value: L) {
+        match self.0.entry(key) {
+            hash_map::Entry::Vacant(e) => {
+                e.insert(value);
+            },
+            hash_map::Entry::Occupied(mut e) => {
+                e.get_mut().join(&value);
+            },
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, L: Lattice + Clone> Lattice for MapLattice<K, L> {
+    fn join(&mut self, other: &Self) {
+        for (key, value) in &other.0 {
+            if let Some(my_value) = self.0.get_mut(key) {
+                my_value.join(value);
+                continue;
+            }
+
+            self.0.insert(key.clone(), value.clone());
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        let mut result = Some(Ordering::Equal);
+
+        for (key, value) in &self.0 {
+            let ord = match other.0.get(key) {
+                Some(other_value) => value.compare(other_value),
+                None => Some(Ordering::Greater),
+            };
+
+            result = combine(result, ord);
+        }
+
+        if other.0.keys().any(|k| !self.0.contains_key(k)) {
+            result = combine(result, Some(Ordering::Less));
+        }
+
+        result
+    }
+}
+
+impl<A: Lattice, B: Lattice> Pair<A, B> {
+    pub fn new(first: A, second: B) -> Pair<A, B> {
+        Pair(first, second)
+    }
+
+    pub fn first<'a>(&'a self) -> &'a A {
+        &self.0
+    }
+
+    pub fn second<'a>(&'a self) -> &'a B {
+        &self.1
+    }
+
+    pub fn first_mut<'a>(&'a mut self) -> &'a mut A {
+        &mut self.0
+    }
+
+    pub fn second_mut<'a>(&'a mut self) -> &'a mut B {
+        &mut self.1
+    }
+}
+
+impl<A: Lattice, B: Lattice> Lattice for Pair<A, B> {
+    fn join(&mut self, other: &Self) {
+        self.0.join(&other.0);
+        self.1.join(&other.1);
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        combine(self.0.compare(&other.0), self.1.compare(&other.1))
+    }
+}
+
+impl<A: Chain + Clone, B: Lattice + Clone> Lexicographic<A, B> {
+    pub fn new(first: A, second: B) -> Lexicographic<A, B> {
+        Lexicographic(first, second)
+    }
+
+    pub fn first<'a>(&'a self) -> &'a A {
+        &self.0
+    }
+
+    pub fn second<'a>(&'a self) -> &'a B {
+        &self.1
+    }
+}
+
+// The first component has to be a chain: with concurrent first components
+// no join keeps the second one associative.
+impl<A: Chain + Clone, B: Lattice + Clone> Lattice for Lexicographic<A, B> {
+    fn join(&mut self, other: &Self) {
+        match self.0.compare(&other.0) {
+            Some(Ordering::Less) => {
+                self.0 = other.0.clone();
+                self.1 = other.1.clone();
+            },
+            Some(Ordering::Equal) => {
+                self.1.join(&other.1);
+            },
+            Some(Ordering::Greater) | None => {},
+        }
+    }
+
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        match self.0.compare(&other.0) {
+            Some(Ordering::Equal) => self.1.compare(&other.1),
+            ord => ord,
+        }
+    }
+}
+
+impl<A: Chain + Clone, B: Chain + Clone> Chain for Lexicographic<A, B> {}
+
+impl<T: Ord + Clone> DiffRDT for MaxLattice<T> {
+    type Summary = T;
+    type Delta = Option<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.0.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if self.0 > *summary { Some(self.0.clone()) } else { None }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref value) = *delta {
+            self.join(&MaxLattice(value.clone()));
+        }
+    }
+}
+
+impl<T: Ord + Clone> DiffRDT for MinLattice<T> {
+    type Summary = T;
+    type Delta = Option<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.0.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if self.0 < *summary { Some(self.0.clone()) } else { None }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref value) = *delta {
+            self.join(&MinLattice(value.clone()));
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> DiffRDT for SetUnion<T> {
+    type Summary = MerkleTree;
+    type Delta = SetUnion<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.0.iter().map(item_hash).collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().diff(summary);
+
+        SetUnion(self.0.iter().filter(|v| in_buckets(&buckets, item_hash(v))).cloned().collect())
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.join(delta);
+    }
+}
+
+// Values are folded into the tree as (key, digest) items, and a differing
+// entry is sent whole.
+impl<K: Hash + Eq + Clone, L: Lattice + Clone + Digest> DiffRDT for MapLattice<K, L> {
+    type Summary = MerkleTree;
+    type Delta = MapLattice<K, L>;
+
+    fn summary(&self) -> Self::Summary {
+        self.0.iter().map(|(key, value)| item_hash(&(key, value.digest()))).collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().diff(summary);
+
+        MapLattice(self.0.iter()
+            .filter(|&(key, value)| in_buckets(&buckets, item_hash(&(key, value.digest()))))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.join(delta);
+    }
+}
+
+impl<A: Lattice + DiffRDT, B: Lattice + DiffRDT> DiffRDT for Pair<A, B> {
+    type Summary = (A::Summary, B::Summary);
+    type Delta = (A::Delta, B::Delta);
+
+    fn summary(&self) -> Self::Summary {
+        (self.0.summary(), self.1.summary())
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        (self.0.diff(&summary.0), self.1.diff(&summary.1))
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.0.apply_diff(&delta.0);
+        self.1.apply_diff(&delta.1);
+    }
+}
+
+// Which second component wins depends on how the first ones compare, so a
+// differing peer is sent both.
+impl<A: Chain + Clone + Digest, B: Lattice + Clone + Digest> DiffRDT for Lexicographic<A, B> {
+    type Summary = u64;
+    type Delta = Option<Lexicographic<A, B>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.digest()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if self.digest() == *summary { None } else { Some(self.clone()) }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref other) = *delta {
+            self.join(other);
+        }
+    }
+}
+
+impl<T: Ord + Clone + Hash> Digest for MaxLattice<T> {
+    fn digest(&self) -> u64 {
+        item_hash(&self.0)
+    }
+}
+
+impl<T: Ord + Clone + Hash> Digest for MinLattice<T> {
+    fn digest(&self) -> u64 {
+        item_hash(&self.0)
+    }
+}
+
+impl<T: Hash + Eq + Clone> Digest for SetUnion<T> {
+    fn digest(&self) -> u64 {
+        self.summary().root()
+    }
+}
+
+impl<K: Hash + Eq + Clone, L: Lattice + Clone + Digest> Digest for MapLattice<K, L> {
+    fn digest(&self) -> u64 {
+        self.summary().root()
+    }
+}
+
+impl<A: Lattice + Digest, B: Lattice + Digest> Digest for Pair<A, B> {
+    fn digest(&self) -> u64 {
+        item_hash(&(self.0.digest(), self.1.digest()))
+    }
+}
+
+impl<A: Chain + Clone + Digest, B: Lattice + Clone + Digest> Digest for Lexicographic<A, B> {
+    fn digest(&self) -> u64 {
+        item_hash(&(self.0.digest(), self.1.digest()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MaxLattice, MinLattice, SetUnion, MapLattice, Pair, Lexicographic};
+
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    use core::{Lattice, StateRDT, DiffRDT};
+
+    fn set_of(values: Vec<i32>) -> SetUnion<i32> {
+        let mut set = SetUnion::new();
+
+        for v in values {
+            set.insert(v);
+        }
+
+        set
+    }
+
+    #[test]
+    fn join_max_lattice() {
+        let mut l1 = MaxLattice::new(5);
+        let l2 = MaxLattice::new(7);
+
+        assert_eq!(l1.compare(&l2), Some(Ordering::Less));
+
+        l1.join(&l2);
+
+        assert_eq!(*l1.value(), 7);
+        assert_eq!(l1.compare(&l2), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn join_min_lattice() {
+        let mut l1 = MinLattice::new(5);
+        let l2 = MinLattice::new(7);
+
+        assert_eq!(l1.compare(&l2), Some(Ordering::Greater));
+
+        l1.join(&l2);
+
+        assert_eq!(*l1.value(), 5);
+    }
+
+    #[test]
+    fn join_set_union() {
+        let mut s1 = set_of(vec![1, 2]);
+        let s2 = set_of(vec![2, 3]);
+
+        assert_eq!(s1.compare(&s2), None);
+
+        s1.merge(&s2);
+
+        assert_eq!(*s1.value(), HashSet::from_iter(vec![1, 2, 3]));
+        assert_eq!(s1.compare(&s2), Some(Ordering::Greater));
+    }
+
+    #[test]
+    fn join_map_lattice() {
+        let mut m1 = MapLattice::new();
+        let mut m2 = MapLattice::new();
+
+        m1.insert("a", MaxLattice::new(5));
+        m2.insert("a", MaxLattice::new(3));
+        m2.insert("b", MaxLattice::new(1));
+
+        assert_eq!(m1.compare(&m2), None);
+
+        m1.join(&m2);
+
+        assert_eq!(*m1.get(&"a").unwrap().value(), 5);
+        assert_eq!(*m1.get(&"b").unwrap().value(), 1);
+        assert_eq!(m1.compare(&m2), Some(Ordering::Greater));
+    }
+
+    #[test]
+    fn join_pair() {
+        let mut p1 = Pair::new(MaxLattice::new(5), set_of(vec![1]));
+        let p2 = Pair::new(MaxLattice::new(3), set_of(vec![1, 2]));
+
+        assert_eq!(p1.compare(&p2), None);
+
+        p1.join(&p2);
+
+        assert_eq!(*p1.first().value(), 5);
+        assert_eq!(*p1.second().value(), HashSet::from_iter(vec![1, 2]));
+    }
+
+    #[test]
+    fn join_lexicographic() {
+        let mut l1 = Lexicographic::new(MaxLattice::new(1), set_of(vec![1]));
+        let l2 = Lexicographic::new(MaxLattice::new(2), set_of(vec![2]));
+        let l3 = Lexicographic::new(MaxLattice::new(2), set_of(vec![3]));
+
+        l1.join(&l2);
+
+        assert_eq!(*l1.first().value(), 2);
+        assert_eq!(*l1.second().value(), HashSet::from_iter(vec![2]));
+
+        l1.join(&l3);
+
+        assert_eq!(*l1.second().value(), HashSet::from_iter(vec![2, 3]));
+    }
+
+    #[test]
+    fn join_lexicographic_is_associative_and_commutative() {
+        let values = vec![
+            Lexicographic::new(MaxLattice::new(1), set_of(vec![1, 2])),
+            Lexicographic::new(MaxLattice::new(2), set_of(vec![3])),
+            Lexicographic::new(MaxLattice::new(2), set_of(vec![4])),
+            Lexicographic::new(MaxLattice::new(1), set_of(vec![5])),
+        ];
+
+        let join = |a: &Lexicographic<_, _>, b: &Lexicographic<_, _>| {
+            let mut result = a.clone();
+            result.join(b);
+            result
+        };
+
+        for a in &values {
+            for b in &values {
+                assert_eq!(join(a, b), join(b, a));
+
+                for c in &values {
+                    assert_eq!(join(&join(a, b), c), join(a, &join(b, c)));
+                }
+
+                let ord = a.compare(b);
+
+                if ord == Some(Ordering::Greater) || ord == Some(Ordering::Equal) {
+                    assert_eq!(join(a, b), *a);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn diff_lattices() {
+        let mut m1 = MapLattice::new();
+        let mut m2 = MapLattice::new();
+
+        for i in 0..100 {
+            m1.insert(i, set_of(vec![i]));
+        }
+
+        m2.join(&m1);
+        m2.insert(5, set_of(vec![500]));
+        m2.insert(200, set_of(vec![200]));
+
+        let delta = m2.diff(&m1.summary());
+
+        assert!(delta.iter().count() < 10);
+
+        m1.apply_diff(&delta);
+
+        assert_eq!(m1, m2);
+        assert!(m2.diff(&m1.summary()).iter().next().is_none());
+
+        let mut p1 = Pair::new(MaxLattice::new(1), set_of(vec![1]));
+        let p2 = Pair::new(MaxLattice::new(3), set_of(vec![2]));
+
+        p1.apply_diff(&p2.diff(&p1.summary()));
+
+        assert_eq!(*p1.first().value(), 3);
+        assert_eq!(p1.second(), &set_of(vec![1, 2]));
+
+        let mut l1 = Lexicographic::new(MaxLattice::new(1), set_of(vec![1]));
+        let l2 = Lexicographic::new(MaxLattice::new(2), set_of(vec![2]));
+
+        l1.apply_diff(&l2.diff(&l1.summary()));
+
+        assert_eq!(l1, l2);
+        assert!(l2.diff(&l1.summary()).is_none());
+    }
+}
@@ -3,15 +3,54 @@
 
 extern crate chrono;
 extern crate rustc_serialize;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
+extern crate siphasher;
 extern crate uuid;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
 
 mod core;
+mod clock;
+mod hashing;
+mod lattices;
 mod counters;
 mod registers;
 mod sets;
+mod bags;
+mod hyperloglog;
+mod topk;
+mod merkle;
+mod reconcile;
 mod maps;
+mod graphs;
+mod lists;
+mod logs;
+mod text;
+mod json;
+mod trees;
+mod transactions;
+mod storage;
 
-pub use counters::{GCounter, PNCounter};
-pub use registers::LWWRegister;
-pub use sets::{GSet, TwoPhaseSet, ObserveRemoveSet};
-pub use maps::ObserveRemoveMap;
+pub use core::{StateRDT, OperationRDT, Lattice, Chain, ValueFactory, BatchRDT, BatchBuilder, DiffRDT};
+pub use lattices::{MaxLattice, MinLattice, SetUnion, MapLattice, Pair, Lexicographic};
+pub use counters::{GCounter, PNCounter, WindowedCounter};
+pub use registers::{LWWRegister, MaxRegister, MinRegister};
+pub use sets::{GSet, TwoPhaseSet, ObserveRemoveSet, SortedSet};
+pub use bags::ORBag;
+pub use hyperloglog::HyperLogLog;
+pub use topk::TopK;
+pub use merkle::{MerkleTree, MerklePath, Digest};
+pub use reconcile::{Reconcilable, Reconciler, ReconcileMessage, HashRange};
+pub use maps::{ObserveRemoveMap, OrderedObserveRemoveMap, MapStore};
+#[cfg(feature = "sqlite")]
+pub use maps::{SqliteObserveRemoveMap, SqliteMapDelta};
+pub use graphs::ORGraph;
+pub use clock::{LamportClock, LamportTimestamp, VersionVector};
+pub use lists::{RGAList, MoveList};
+pub use logs::AppendLog;
+pub use text::{RichText, Mark, MarkType, Span};
+pub use json::{JsonDoc, PathSegment};
+pub use trees::MoveTree;
+pub use transactions::{AtomicReplica, AtomicOperation, AtomicTransaction};
+pub use storage::{Storage, StorageError, MemoryStorage, FileStorage, Persistent};
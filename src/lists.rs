@@ -0,0 +1,779 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::slice;
+
+use uuid::Uuid;
+
+use clock::{LamportClock, LamportTimestamp};
+use core::{StateRDT, OperationRDT, DiffRDT};
+use merkle::{MerkleTree, Digest, item_hash, in_buckets};
+use registers::{MaxRegister, SetMaxRegisterOperation};
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+struct RGAElement<T> {
+    id: LamportTimestamp,
+    origin: Option<LamportTimestamp>,
+    value: T,
+    deleted: bool,
+}
+
+// Operations that refer to an element which hasn't arrived yet are kept in
+// `pending` and applied once it does.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct RGAList<T: Clone> {
+    elements: Vec<RGAElement<T>>,
+    clock: LamportClock,
+    pending: Vec<RGAListOperation<T>>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum RGAListOperation<T> {
+    Insert(Option<LamportTimestamp>, LamportTimestamp, T),
+    Remove(LamportTimestamp),
+}
+
+// An item sits in the slot with the greatest Lamport id it has been given.
+// Slot ids come from the list's own clock, so a local move always wins over
+// every move this replica has already seen.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+struct MoveListItem<T> {
+    value: T,
+    position: MaxRegister<LamportTimestamp>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MoveList<T: Clone> {
+    slots: RGAList<Uuid>,
+    items: HashMap<Uuid, MoveListItem<T>>,
+    removed: HashSet<Uuid>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum MoveListOperation<T> {
+    Insert(Uuid, T, RGAListOperation<Uuid>, SetMaxRegisterOperation<LamportTimestamp>),
+    Move(Uuid, RGAListOperation<Uuid>, SetMaxRegisterOperation<LamportTimestamp>),
+    Remove(Uuid),
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MoveListDelta<T> {
+    slots: Vec<RGAListOperation<Uuid>>,
+    items: Vec<(Uuid, T, MaxRegister<LamportTimestamp>)>,
+    removed: Vec<Uuid>,
+}
+
+pub struct Iter<'a, T: 'a> {
+    elements: slice::Iter<'a, RGAElement<T>>,
+}
+
+pub struct Entries<'a, T: 'a> {
+    elements: slice::Iter<'a, RGAElement<T>>,
+}
+
+impl<'a, T> Iterator for Entries<'a, T> {
+    type Item = (&'a LamportTimestamp, &'a T, bool);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.elements.next().map(|e| (&e.id, &e.value, e.deleted))
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (&'a LamportTimestamp, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(element) = self.elements.next() {
+            if !element.deleted {
+                return Some((&element.id, &element.value));
+            }
+        }
+
+        None
+    }
+}
+
+impl<T: Clone> RGAList<T> {
+    pub fn new() -> RGAList<T> {
+        RGAList {
+            elements: Vec::new(),
+            clock: LamportClock::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.iter().filter(|e| !e.deleted).count()
+    }
+
+    pub fn iter<'a>(&'a self) -> Iter<'a, T> {
+        Iter {
+            elements: self.elements.iter(),
+        }
+    }
+
+    pub fn entries<'a>(&'a self) -> Entries<'a, T> {
+        Entries {
+            elements: self.elements.iter(),
+        }
+    }
+
+    pub fn value(&self) -> Vec<T> {
+        self.iter().map(|(_, v)| v.clone()).collect()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.iter().nth(index).map(|(_, v)| v)
+    }
+
+    pub fn id_at(&self, index: usize) -> Option<LamportTimestamp> {
+        self.iter().nth(index).map(|(id, _)| id.clone())
+    }
+
+    pub fn get_by_id(&self, id: &LamportTimestamp) -> Option<&T> {
+        self.position(id)
+            .map(|pos| &self.elements[pos])
+            .and_then(|e| if e.deleted { None } else { Some(&e.value) })
+    }
+
+    pub fn insert(&mut self, index: usize, value: T) -> Option<RGAListOperation<T>> {
+        let origin = if index == 0 {
+            None
+        }
+        else {
+            match self.id_at(index - 1) {
+                Some(id) => Some(id),
+                None => return None,
+            }
+        };
+
+        let op = RGAListOperation::Insert(origin, self.clock.tick(), value);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn push(&mut self, value: T) -> RGAListOperation<T> {
+        let index = self.len();
+
+        self.insert(index, value).unwrap()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<RGAListOperation<T>> {
+        let op = match self.id_at(index) {
+            Some(id) => RGAListOperation::Remove(id),
+            None => return None,
+        };
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn merge_with<F>(&mut self, other: &RGAList<T>, mut merge_value: F)
+        where F: FnMut(&mut T, &T)
+    {
+        // Every element's origin precedes it in the sequence, so walking the
+        // other list in order always finds the origin already integrated.
+        for element in &other.elements {
+            match self.position(&element.id) {
+                Some(pos) => {
+                    let my_element = &mut self.elements[pos];
+
+                    my_element.deleted = my_element.deleted || element.deleted;
+                    merge_value(&mut my_element.value, &element.value);
+                },
+                None => {
+                    self.integrate(&element.origin, &element.id, &element.value);
+
+                    if element.deleted {
+                        self.delete(&element.id);
+                    }
+                },
+            }
+        }
+
+        self.pending.extend(other.pending.iter().cloned());
+        self.apply_pending();
+    }
+
+    fn position(&self, id: &LamportTimestamp) -> Option<usize> {
+        self.elements.iter().position(|e| e.id == *id)
+    }
+
+    // Returns false, leaving the list alone, if the op refers to an element
+    // that hasn't arrived yet.
+    fn try_apply(&mut self, op: &RGAListOperation<T>) -> bool {
+        match op {
+            &RGAListOperation::Insert(ref origin, ref id, ref value) => self.integrate(origin, id, value),
+            &RGAListOperation::Remove(ref id) => self.delete(id),
+        }
+    }
+
+    fn apply_pending(&mut self) {
+        loop {
+            let pending = mem::replace(&mut self.pending, Vec::new());
+            let count = pending.len();
+
+            for op in pending {
+                if !self.try_apply(&op) {
+                    self.pending.push(op);
+                }
+            }
+
+            if self.pending.len() == count {
+                break;
+            }
+        }
+    }
+
+    fn integrate(&mut self, origin: &Option<LamportTimestamp>, id: &LamportTimestamp, value: &T) -> bool {
+        if self.position(id).is_some() {
+            return true;
+        }
+
+        let mut pos = match *origin {
+            Some(ref origin) => match self.position(origin) {
+                Some(pos) => pos + 1,
+                None => return false,
+            },
+            None => 0,
+        };
+
+        self.clock.observe(id);
+
+        while pos < self.elements.len() && self.elements[pos].id > *id {
+            pos += 1;
+        }
+
+        self.elements.insert(pos, RGAElement {
+            id: id.clone(),
+            origin: origin.clone(),
+            value: value.clone(),
+            deleted: false,
+        });
+
+        true
+    }
+
+    fn delete(&mut self, id: &LamportTimestamp) -> bool {
+        match self.position(id) {
+            Some(pos) => {
+                self.elements[pos].deleted = true;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl<T: Clone> OperationRDT for RGAList<T> {
+    type Operation = RGAListOperation<T>;
+
+    // Peers may deliver operations twice or out of order; an op whose
+    // origin or target is missing waits in `pending`.
+    fn apply(&mut self, op: &Self::Operation) {
+        if self.try_apply(op) {
+            self.apply_pending();
+        }
+        else {
+            self.pending.push(op.clone());
+        }
+    }
+}
+
+impl<T: Clone> StateRDT for RGAList<T> {
+    fn merge(&mut self, other: &Self) {
+        self.merge_with(other, |_, _| ());
+    }
+}
+
+// Every element is an item of the summary tree, and its deletion another.
+impl<T: Clone> DiffRDT for RGAList<T> {
+    type Summary = MerkleTree;
+    type Delta = Vec<RGAListOperation<T>>;
+
+    fn summary(&self) -> Self::Summary {
+        let inserted = self.elements.iter().map(|e| item_hash(&e.id));
+        let deleted = self.elements.iter().filter(|e| e.deleted).map(|e| item_hash(&(&e.id, true)));
+
+        inserted.chain(deleted).collect()
+    }
+
+    // Elements follow their origins in the sequence, so the inserts can be
+    // applied in the order they are listed.
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().diff(summary);
+
+        let inserts = self.elements.iter()
+            .filter(|e| in_buckets(&buckets, item_hash(&e.id)))
+            .map(|e| RGAListOperation::Insert(e.origin.clone(), e.id.clone(), e.value.clone()));
+        let removes = self.elements.iter()
+            .filter(|e| e.deleted && in_buckets(&buckets, item_hash(&(&e.id, true))))
+            .map(|e| RGAListOperation::Remove(e.id.clone()));
+
+        inserts.chain(removes).collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        for op in delta {
+            self.apply(op);
+        }
+    }
+}
+
+impl<T: Clone> Digest for RGAList<T> {
+    fn digest(&self) -> u64 {
+        self.summary().root()
+    }
+}
+
+impl<T: Clone> MoveList<T> {
+    pub fn new() -> MoveList<T> {
+        MoveList {
+            slots: RGAList::new(),
+            items: HashMap::new(),
+            removed: HashSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order().len()
+    }
+
+    pub fn value(&self) -> Vec<T> {
+        self.order().iter().map(|&(_, ref id)| self.items[id].value.clone()).collect()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.order().get(index).map(|&(_, ref id)| &self.items[id].value)
+    }
+
+    pub fn insert(&mut self, index: usize, value: T) -> Option<MoveListOperation<T>> {
+        let order = self.order();
+
+        if index > order.len() {
+            return None;
+        }
+
+        let id = Uuid::new_v4();
+        let (slot_op, position_op) = self.place(&order, index, &id);
+        let op = MoveListOperation::Insert(id, value, slot_op, position_op);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn move_item(&mut self, from: usize, to: usize) -> Option<MoveListOperation<T>> {
+        let mut order = self.order();
+
+        if from >= order.len() || to >= order.len() {
+            return None;
+        }
+
+        let (_, id) = order.remove(from);
+        let (slot_op, position_op) = self.place(&order, to, &id);
+        let op = MoveListOperation::Move(id, slot_op, position_op);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<MoveListOperation<T>> {
+        let op = match self.order().get(index) {
+            Some(&(_, ref id)) => MoveListOperation::Remove(id.clone()),
+            None => return None,
+        };
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    // Returns the slot index and item id of every visible item, in list
+    // order. A slot is only visible if its item's position still points to it.
+    fn order(&self) -> Vec<(usize, Uuid)> {
+        self.slots.iter()
+            .enumerate()
+            .filter(|&(_, (slot, item_id))| {
+                !self.removed.contains(item_id) &&
+                    self.items.get(item_id)
+                        .map_or(false, |item| item.position.value() == Some(slot))
+            })
+            .map(|(index, (_, item_id))| (index, item_id.clone()))
+            .collect()
+    }
+
+    fn merge_item(&mut self, id: &Uuid, value: &T, position: &MaxRegister<LamportTimestamp>) {
+        let item = self.items.entry(id.clone()).or_insert_with(|| MoveListItem {
+            value: value.clone(),
+            position: MaxRegister::new(),
+        });
+
+        item.position.merge(position);
+    }
+
+    fn place(&mut self, order: &[(usize, Uuid)], index: usize, id: &Uuid)
+        -> (RGAListOperation<Uuid>, SetMaxRegisterOperation<LamportTimestamp>)
+    {
+        let slot_index = if index == 0 { 0 } else { order[index - 1].0 + 1 };
+
+        let slot_op = self.slots.insert(slot_index, id.clone()).unwrap();
+        let slot_id = self.slots.id_at(slot_index).unwrap();
+
+        let mut position = MaxRegister::new();
+        let position_op = position.set(slot_id).unwrap();
+
+        (slot_op, position_op)
+    }
+}
+
+impl<T: Clone> OperationRDT for MoveList<T> {
+    type Operation = MoveListOperation<T>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        use self::MoveListOperation::{Insert, Move, Remove};
+
+        match op {
+            &Insert(ref id, ref value, ref slot_op, ref position_op) => {
+                self.slots.apply(slot_op);
+
+                let item = self.items.entry(id.clone()).or_insert_with(|| MoveListItem {
+                    value: value.clone(),
+                    position: MaxRegister::new(),
+                });
+
+                item.position.apply(position_op);
+            },
+            &Move(ref id, ref slot_op, ref position_op) => {
+                self.slots.apply(slot_op);
+
+                if let Some(item) = self.items.get_mut(id) {
+                    item.position.apply(position_op);
+                }
+            },
+            &Remove(ref id) => {
+                self.removed.insert(id.clone());
+            },
+        }
+    }
+}
+
+impl<T: Clone> StateRDT for MoveList<T> {
+    fn merge(&mut self, other: &Self) {
+        self.slots.merge(&other.slots);
+
+        for (id, item) in &other.items {
+            self.merge_item(id, &item.value, &item.position);
+        }
+
+        self.removed.extend(other.removed.iter().cloned());
+    }
+}
+
+impl<T: Clone> DiffRDT for MoveList<T> {
+    type Summary = (MerkleTree, MerkleTree);
+    type Delta = MoveListDelta<T>;
+
+    fn summary(&self) -> Self::Summary {
+        let positions = self.items.iter().map(|(id, item)| item_hash(&(id, item.position.digest())));
+        let removed = self.removed.iter().map(item_hash);
+
+        (self.slots.summary(), positions.chain(removed).collect())
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().1.diff(&summary.1);
+
+        MoveListDelta {
+            slots: self.slots.diff(&summary.0),
+            items: self.items.iter()
+                .filter(|&(id, item)| in_buckets(&buckets, item_hash(&(id, item.position.digest()))))
+                .map(|(id, item)| (id.clone(), item.value.clone(), item.position.clone()))
+                .collect(),
+            removed: self.removed.iter()
+                .filter(|id| in_buckets(&buckets, item_hash(id)))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.slots.apply_diff(&delta.slots);
+
+        for &(ref id, ref value, ref position) in &delta.items {
+            self.merge_item(id, value, position);
+        }
+
+        self.removed.extend(delta.removed.iter().cloned());
+    }
+}
+
+impl<T: Clone> Digest for MoveList<T> {
+    fn digest(&self) -> u64 {
+        let (slots, items) = self.summary();
+
+        item_hash(&(slots.root(), items.root()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RGAList, MoveList};
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    #[test]
+    fn make_rga_list() {
+        let list: RGAList<i32> = RGAList::new();
+
+        assert!(list.value().is_empty());
+    }
+
+    #[test]
+    fn insert_rga_list() {
+        let mut list = RGAList::new();
+
+        list.push(1);
+        list.push(3);
+        list.insert(1, 2).unwrap();
+
+        assert!(list.insert(4, 5).is_none());
+        assert_eq!(list.value(), vec![1, 2, 3]);
+        assert_eq!(list.get(1), Some(&2));
+    }
+
+    #[test]
+    fn remove_rga_list() {
+        let mut list = RGAList::new();
+
+        list.push(1);
+        list.push(2);
+        list.remove(0).unwrap();
+
+        assert!(list.remove(1).is_none());
+        assert_eq!(list.value(), vec![2]);
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn apply_rga_list_concurrent_inserts() {
+        let mut l1 = RGAList::new();
+        let mut l2 = RGAList::new();
+
+        let op1 = l1.push('a');
+        l2.apply(&op1);
+
+        let op2 = l1.insert(1, 'b').unwrap();
+        let op3 = l2.insert(1, 'c').unwrap();
+        let op4 = l2.insert(2, 'd').unwrap();
+
+        l1.apply(&op3);
+        l1.apply(&op4);
+        l2.apply(&op2);
+
+        assert_eq!(l1.value(), l2.value());
+        assert_eq!(l1.len(), 4);
+    }
+
+    #[test]
+    fn apply_rga_list_concurrent_remove() {
+        let mut l1 = RGAList::new();
+        let mut l2 = RGAList::new();
+
+        let op1 = l1.push('a');
+        l2.apply(&op1);
+
+        let op2 = l1.remove(0).unwrap();
+        let op3 = l2.insert(1, 'b').unwrap();
+
+        l1.apply(&op3);
+        l2.apply(&op2);
+
+        assert_eq!(l1.value(), vec!['b']);
+        assert_eq!(l2.value(), vec!['b']);
+    }
+
+    #[test]
+    fn apply_rga_list_ops_out_of_order() {
+        let mut l1 = RGAList::new();
+        let mut l2 = RGAList::new();
+
+        let op1 = l1.push('a');
+        let op2 = l1.push('b');
+        let op3 = l1.remove(0).unwrap();
+
+        l2.apply(&op3);
+        l2.apply(&op2);
+        l2.apply(&op2);
+
+        assert!(l2.value().is_empty());
+
+        l2.apply(&op1);
+
+        assert_eq!(l2.value(), vec!['b']);
+        assert_eq!(l1.value(), l2.value());
+    }
+
+    #[test]
+    fn merge_rga_list() {
+        let mut l1 = RGAList::new();
+        let mut l2 = RGAList::new();
+
+        l1.push(1);
+        l1.push(2);
+        l2.merge(&l1);
+
+        l1.insert(1, 3).unwrap();
+        l2.remove(0).unwrap();
+        l2.push(4);
+
+        l1.merge(&l2);
+        l2.merge(&l1);
+
+        assert_eq!(l1.value(), vec![3, 2, 4]);
+        assert_eq!(l2.value(), vec![3, 2, 4]);
+    }
+
+    #[test]
+    fn diff_rga_list() {
+        let mut l1 = RGAList::new();
+        let mut l2 = RGAList::new();
+
+        l1.push(1);
+        l1.push(2);
+        l2.merge(&l1);
+
+        l2.insert(1, 3).unwrap();
+        l2.insert(2, 4).unwrap();
+        l2.remove(0).unwrap();
+
+        let delta = l2.diff(&l1.summary());
+
+        assert_eq!(delta.len(), 3);
+
+        l1.apply_diff(&delta);
+
+        assert_eq!(l1.value(), vec![3, 4, 2]);
+        assert!(l1.diff(&l2.summary()).is_empty());
+    }
+
+    #[test]
+    fn insert_move_list() {
+        let mut list = MoveList::new();
+
+        list.insert(0, 'a').unwrap();
+        list.insert(1, 'c').unwrap();
+        list.insert(1, 'b').unwrap();
+
+        assert!(list.insert(4, 'x').is_none());
+        assert_eq!(list.value(), vec!['a', 'b', 'c']);
+    }
+
+    #[test]
+    fn move_move_list_items() {
+        let mut list = MoveList::new();
+
+        for c in "abcd".chars() {
+            let index = list.len();
+            list.insert(index, c).unwrap();
+        }
+
+        list.move_item(0, 3).unwrap();
+        assert_eq!(list.value(), vec!['b', 'c', 'd', 'a']);
+
+        list.move_item(2, 0).unwrap();
+        assert_eq!(list.value(), vec!['d', 'b', 'c', 'a']);
+
+        list.remove(1).unwrap();
+        assert_eq!(list.value(), vec!['d', 'c', 'a']);
+        assert!(list.move_item(0, 3).is_none());
+    }
+
+    #[test]
+    fn apply_concurrent_moves_of_same_item() {
+        let mut l1 = MoveList::new();
+        let mut l2 = MoveList::new();
+
+        for c in "abc".chars() {
+            let index = l1.len();
+            let op = l1.insert(index, c).unwrap();
+            l2.apply(&op);
+        }
+
+        let op1 = l1.move_item(0, 2).unwrap();
+        let op2 = l2.move_item(0, 1).unwrap();
+
+        l1.apply(&op2);
+        l2.apply(&op1);
+
+        assert_eq!(l1.value(), l2.value());
+        assert_eq!(l1.len(), 3);
+    }
+
+    #[test]
+    fn move_after_remote_moves() {
+        let mut l1 = MoveList::new();
+        let mut l2 = MoveList::new();
+
+        for c in "abc".chars() {
+            let index = l2.len();
+            let op = l2.insert(index, c).unwrap();
+            l1.apply(&op);
+        }
+
+        for _ in 0..5 {
+            let op = l2.move_item(0, 2).unwrap();
+            l1.apply(&op);
+        }
+
+        let op = l1.move_item(2, 0).unwrap();
+
+        assert_eq!(l1.value(), vec!['b', 'c', 'a']);
+
+        l2.apply(&op);
+
+        assert_eq!(l2.value(), l1.value());
+    }
+
+    #[test]
+    fn merge_move_list_state() {
+        let mut l1 = MoveList::new();
+        let mut l2 = MoveList::new();
+
+        l1.insert(0, 1).unwrap();
+        l1.insert(1, 2).unwrap();
+        l2.merge(&l1);
+
+        l1.move_item(1, 0).unwrap();
+        l2.insert(2, 3).unwrap();
+        l2.remove(0).unwrap();
+
+        l1.merge(&l2);
+        l2.merge(&l1);
+
+        assert_eq!(l1.value(), vec![2, 3]);
+        assert_eq!(l2.value(), vec![2, 3]);
+    }
+
+    #[test]
+    fn diff_move_list_state() {
+        let mut l1 = MoveList::new();
+        let mut l2 = MoveList::new();
+
+        l1.insert(0, 1).unwrap();
+        l1.insert(1, 2).unwrap();
+        l2.merge(&l1);
+
+        l1.move_item(1, 0).unwrap();
+        l2.insert(2, 3).unwrap();
+
+        l1.apply_diff(&l2.diff(&l1.summary()));
+        l2.apply_diff(&l1.diff(&l2.summary()));
+
+        assert_eq!(l1.value(), vec![2, 1, 3]);
+        assert_eq!(l2.value(), vec![2, 1, 3]);
+    }
+}
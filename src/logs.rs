@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+
+use uuid::Uuid;
+
+use clock::{LamportClock, VersionVector};
+use core::{StateRDT, OperationRDT, DiffRDT, Lattice};
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct AppendLogOperation<T> {
+    lamport: u64,
+    replica: Uuid,
+    seq: u64,
+    value: T,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct AppendLog<T: Clone> {
+    id: Uuid,
+    clock: LamportClock,
+    entries: BTreeMap<(u64, Uuid, u64), T>,
+    heads: VersionVector,
+    base: VersionVector,
+    pending: BTreeMap<Uuid, BTreeMap<u64, AppendLogOperation<T>>>,
+}
+
+impl<T> AppendLogOperation<T> {
+    pub fn replica(&self) -> Uuid {
+        self.replica.clone()
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl<T: Clone> AppendLog<T> {
+    pub fn new() -> AppendLog<T> {
+        AppendLog {
+            id: Uuid::new_v4(),
+            clock: LamportClock::new(),
+            entries: BTreeMap::new(),
+            heads: VersionVector::new(),
+            base: VersionVector::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn value(&self) -> Vec<&T> {
+        self.entries.values().collect()
+    }
+
+    pub fn cursor(&self) -> VersionVector {
+        self.heads.clone()
+    }
+
+    // Entries not yet covered by the cursor, in log order. Advance the
+    // cursor by joining it with `cursor()` once they have been consumed.
+    pub fn since(&self, cursor: &VersionVector) -> Vec<&T> {
+        self.entries.iter()
+            .filter(|&(&(_, ref replica, seq), _)| !cursor.contains(replica, seq))
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    pub fn append(&mut self, value: T) -> AppendLogOperation<T> {
+        let op = AppendLogOperation {
+            lamport: self.clock.tick().counter(),
+            replica: self.id.clone(),
+            seq: self.heads.get(&self.id) + 1,
+            value: value,
+        };
+
+        self.apply(&op);
+
+        op
+    }
+
+    // The stable cut is typically the meet of every replica's cursor; any
+    // entry at or below it has been seen everywhere and can be dropped.
+    pub fn compact(&mut self, stable: &VersionVector) {
+        self.base.join(stable);
+        self.prune();
+    }
+
+    // Heads only cover a contiguous prefix of each replica's entries; an
+    // entry that arrives past a gap waits in `pending` until the gap fills.
+    fn deliver(&mut self, replica: &Uuid) {
+        loop {
+            let next = self.heads.get(replica) + 1;
+
+            let op = match self.pending.get_mut(replica).and_then(|ops| ops.remove(&next)) {
+                Some(op) => op,
+                None => break,
+            };
+
+            self.insert(&op);
+        }
+
+        if self.pending.get(replica).map_or(false, |ops| ops.is_empty()) {
+            self.pending.remove(replica);
+        }
+    }
+
+    fn insert(&mut self, op: &AppendLogOperation<T>) {
+        self.clock.observe_counter(op.lamport);
+        self.heads.observe(&op.replica, op.seq);
+        self.entries.insert((op.lamport, op.replica.clone(), op.seq), op.value.clone());
+    }
+
+    fn prune(&mut self) {
+        let base = &self.base;
+
+        let compacted = self.entries.keys()
+            .filter(|&&(_, ref replica, seq)| base.contains(replica, seq))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in compacted {
+            self.entries.remove(&key);
+        }
+
+        self.heads.join(&self.base);
+
+        let replicas = self.pending.keys().cloned().collect::<Vec<_>>();
+
+        for replica in replicas {
+            let head = self.heads.get(&replica);
+
+            if let Some(ops) = self.pending.get_mut(&replica) {
+                *ops = ops.split_off(&(head + 1));
+            }
+
+            self.deliver(&replica);
+        }
+    }
+}
+
+impl<T: Clone> OperationRDT for AppendLog<T> {
+    type Operation = AppendLogOperation<T>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        if self.heads.contains(&op.replica, op.seq) || self.base.contains(&op.replica, op.seq) {
+            return;
+        }
+
+        if op.seq > self.heads.get(&op.replica) + 1 {
+            self.pending.entry(op.replica.clone())
+                .or_insert(BTreeMap::new())
+                .insert(op.seq, op.clone());
+            return;
+        }
+
+        self.insert(op);
+        self.deliver(&op.replica);
+    }
+}
+
+impl<T: Clone> StateRDT for AppendLog<T> {
+    fn merge(&mut self, other: &Self) {
+        for (&(lamport, ref replica, seq), value) in &other.entries {
+            self.apply(&AppendLogOperation {
+                lamport: lamport,
+                replica: replica.clone(),
+                seq: seq,
+                value: value.clone(),
+            });
+        }
+
+        for ops in other.pending.values() {
+            for op in ops.values() {
+                self.apply(op);
+            }
+        }
+
+        self.base.join(&other.base);
+        self.prune();
+    }
+}
+
+impl<T: Clone> DiffRDT for AppendLog<T> {
+    type Summary = VersionVector;
+    type Delta = Vec<AppendLogOperation<T>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.cursor()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.entries.iter()
+            .filter(|&(&(_, ref replica, seq), _)| !summary.contains(replica, seq))
+            .map(|(&(lamport, ref replica, seq), value)| AppendLogOperation {
+                lamport: lamport,
+                replica: replica.clone(),
+                seq: seq,
+                value: value.clone(),
+            })
+            .collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        for op in delta {
+            self.apply(op);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AppendLog;
+
+    use clock::VersionVector;
+    use core::{StateRDT, OperationRDT, DiffRDT, Lattice};
+
+    #[test]
+    fn make_append_log() {
+        let log: AppendLog<&str> = AppendLog::new();
+
+        assert_eq!(log.len(), 0);
+        assert!(log.value().is_empty());
+    }
+
+    #[test]
+    fn append_log_entries() {
+        let mut log = AppendLog::new();
+
+        let op1 = log.append("login");
+        let op2 = log.append("logout");
+
+        assert_eq!((op1.seq(), op2.seq()), (1, 2));
+        assert_eq!(log.value(), vec![&"login", &"logout"]);
+    }
+
+    #[test]
+    fn apply_concurrent_appends() {
+        let mut l1 = AppendLog::new();
+        let mut l2 = AppendLog::new();
+
+        let op1 = l1.append("a");
+        let op2 = l2.append("b");
+        let op3 = l2.append("c");
+
+        l1.apply(&op2);
+        l1.apply(&op3);
+        l2.apply(&op1);
+        l2.apply(&op1);
+
+        assert_eq!(l1.value(), l2.value());
+        assert_eq!(l1.len(), 3);
+
+        let op4 = l1.append("d");
+
+        assert_eq!(*l1.value()[3], "d");
+        assert_eq!(op4.replica(), l1.id());
+    }
+
+    #[test]
+    fn read_append_log_since_cursor() {
+        let mut l1 = AppendLog::new();
+        let mut l2 = AppendLog::new();
+
+        l1.append(1);
+        l2.merge(&l1);
+
+        let mut cursor = VersionVector::new();
+        cursor.join(&l2.cursor());
+
+        l1.append(2);
+        l2.append(3);
+        l2.merge(&l1);
+
+        assert_eq!(l2.since(&cursor).len(), 2);
+        assert!(l2.since(&l2.cursor()).is_empty());
+    }
+
+    #[test]
+    fn diff_append_log() {
+        let mut l1 = AppendLog::new();
+        let mut l2 = AppendLog::new();
+
+        l1.append(1);
+        l2.merge(&l1);
+        l2.append(2);
+        l2.append(3);
+
+        let delta = l2.diff(&l1.summary());
+
+        assert_eq!(delta.len(), 2);
+
+        l1.apply_diff(&delta);
+
+        assert_eq!(l1.value(), l2.value());
+    }
+
+    #[test]
+    fn apply_append_log_ops_past_a_gap() {
+        let mut l1 = AppendLog::new();
+        let mut l2 = AppendLog::new();
+
+        l1.append(1);
+        let op2 = l1.append(2);
+
+        l2.apply(&op2);
+
+        assert!(l2.value().is_empty());
+        assert_eq!(l2.cursor().get(&l1.id()), 0);
+
+        let delta = l1.diff(&l2.summary());
+
+        assert_eq!(delta.len(), 2);
+
+        l2.apply_diff(&delta);
+
+        assert_eq!(l2.value(), vec![&1, &2]);
+        assert_eq!(l2.cursor().get(&l1.id()), 2);
+        assert!(l1.diff(&l2.summary()).is_empty());
+    }
+
+    #[test]
+    fn compact_append_log() {
+        let mut l1 = AppendLog::new();
+        let mut l2 = AppendLog::new();
+
+        l1.append("a");
+        l1.append("b");
+        l2.merge(&l1);
+
+        let stable = l1.cursor().meet(&l2.cursor());
+
+        l2.append("c");
+        l1.merge(&l2);
+        l1.compact(&stable);
+
+        assert_eq!(l1.value(), vec![&"c"]);
+
+        l1.merge(&l2);
+        l2.merge(&l1);
+
+        assert_eq!(l1.value(), vec![&"c"]);
+        assert_eq!(l2.value(), vec![&"c"]);
+    }
+}
@@ -0,0 +1,1010 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::hash::Hash;
+use std::mem;
+use std::ops::RangeBounds;
+
+use rustc_serialize::{Encodable, Decodable, Encoder, Decoder};
+use uuid::Uuid;
+
+use core::{StateRDT, OperationRDT, ValueFactory, BatchRDT, DiffRDT};
+use merkle::{MerkleTree, MerklePath, Digest, item_hash, in_buckets};
+
+use sets::{ObserveRemoveSet, ORSetOperation, ORSetBatch};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::{SqliteObserveRemoveMap, SqliteMapDelta};
+
+pub trait MapStore<K, V> {
+    fn get(&self, key: &K) -> Option<&V>;
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V;
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(&'a K, &'a V)> + 'a>
+        where K: 'a, V: 'a;
+}
+
+#[derive(Debug, Clone)]
+pub struct ObserveRemoveMap<K, V, S = HashMap<K, V>>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V>
+{
+    keys: ObserveRemoveSet<K>,
+    values: S,
+    context: V::Context,
+    digests: RefCell<ValueDigests<K>>,
+}
+
+// Each value is folded into the map's Merkle tree as a (key, digest) item.
+// Changing a value only marks its key; the items of marked keys are brought
+// up to date when the tree is next read, where the values are known to
+// have a digest.
+#[derive(Debug, Clone)]
+struct ValueDigests<K: Hash + Eq> {
+    merkle: MerkleTree,
+    items: HashMap<K, u64>,
+    changed: HashSet<K>,
+}
+
+// Maps are encoded without their digests, which are rebuilt on demand
+// after decoding, and without the replica's context, so converged replicas
+// encode the same snapshot. A decoded map starts from the default context
+// until `set_context` is called.
+#[derive(RustcEncodable, RustcDecodable)]
+struct ObserveRemoveMapState<K, S> {
+    keys: K,
+    values: S,
+}
+
+pub type OrderedObserveRemoveMap<K, V> = ObserveRemoveMap<K, V, BTreeMap<K, V>>;
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct ORMapOperation<K, V: OperationRDT> {
+    key: K,
+    key_op: Option<ORSetOperation<K>>,
+    value_ops: Vec<V::Operation>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct ORMapBatch<K, V: OperationRDT> {
+    keys: Option<ORSetBatch<K>>,
+    values: Vec<(K, Vec<V::Operation>)>,
+}
+
+#[must_use]
+pub struct Entry<'m, K, V, S>
+    where K: Hash + Eq + Clone + 'm,
+          V: OperationRDT + ValueFactory<K> + 'm,
+          S: MapStore<K, V> + 'm
+{
+    map: &'m mut ObserveRemoveMap<K, V, S>,
+    key: K,
+    key_op: Option<ORSetOperation<K>>,
+    value: Option<V>,
+    value_ops: Vec<V::Operation>,
+}
+
+impl<K: Hash + Eq> ValueDigests<K> {
+    fn new(changed: HashSet<K>) -> ValueDigests<K> {
+        ValueDigests {
+            merkle: MerkleTree::new(),
+            items: HashMap::new(),
+            changed: changed,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> MapStore<K, V> for HashMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        HashMap::get(self, key)
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        self.entry(key).or_insert_with(f)
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(&'a K, &'a V)> + 'a>
+        where K: 'a, V: 'a
+    {
+        Box::new(HashMap::iter(self))
+    }
+}
+
+impl<K: Ord, V> MapStore<K, V> for BTreeMap<K, V> {
+    fn get(&self, key: &K) -> Option<&V> {
+        BTreeMap::get(self, key)
+    }
+
+    fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        self.entry(key).or_insert_with(f)
+    }
+
+    fn iter<'a>(&'a self) -> Box<Iterator<Item=(&'a K, &'a V)> + 'a>
+        where K: 'a, V: 'a
+    {
+        Box::new(BTreeMap::iter(self))
+    }
+}
+
+impl<K, V> ObserveRemoveMap<K, V>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>
+{
+    pub fn new(context: V::Context) -> ObserveRemoveMap<K, V> {
+        ObserveRemoveMap {
+            keys: ObserveRemoveSet::new(),
+            values: HashMap::new(),
+            context: context,
+            digests: RefCell::new(ValueDigests::new(HashSet::new())),
+        }
+    }
+}
+
+impl<K, V> ObserveRemoveMap<K, V, BTreeMap<K, V>>
+    where K: Hash + Eq + Ord + Clone,
+          V: OperationRDT + ValueFactory<K>
+{
+    pub fn new_ordered(context: V::Context) -> OrderedObserveRemoveMap<K, V> {
+        ObserveRemoveMap {
+            keys: ObserveRemoveSet::new(),
+            values: BTreeMap::new(),
+            context: context,
+            digests: RefCell::new(ValueDigests::new(HashSet::new())),
+        }
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(&K, &V)> {
+        self.values.range(range)
+            .filter(|&(key, _)| self.keys.contains(key))
+            .collect()
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.iter().next()
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.values.iter()
+            .rev()
+            .find(|&(key, _)| self.keys.contains(key))
+    }
+}
+
+impl<K, V, S> ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V>
+{
+    pub fn context<'a>(&'a self) -> &'a V::Context {
+        &self.context
+    }
+
+    pub fn set_context(&mut self, context: V::Context) {
+        self.context = context;
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        return if self.keys.contains(key) {
+            self.values.get(key)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.keys.contains(key)
+    }
+
+    pub fn keys(&self) -> HashSet<K> {
+        self.keys.value()
+    }
+
+    pub fn iter<'b>(&'b self) -> Box<Iterator<Item=(&'b K, &'b V)> + 'b> {
+        let keys = &self.keys;
+
+        Box::new(self.values.iter().filter(move |&(key, _)| keys.contains(key)))
+    }
+
+    pub fn entry<'m>(&'m mut self, key: K) -> Entry<'m, K, V, S> {
+        Entry {
+            map: self,
+            key: key,
+            key_op: None,
+            value: None,
+            value_ops: Vec::new(),
+        }
+    }
+
+    // Nothing can return between changing the value and handing back the
+    // operation here, so unlike an entry the value is updated in place.
+    pub fn update<F>(&mut self, key: K, update_fn: F) -> Option<ORMapOperation<K, V>>
+        where F: FnOnce(&mut V) -> Option<V::Operation>
+    {
+        let key_op = if self.contains_key(&key) {
+            None
+        }
+        else {
+            Some(self.keys.add(key.clone()))
+        };
+
+        let value_op = {
+            let context = &self.context;
+            let value = self.values.get_or_insert_with(key.clone(), || V::create(&key, context));
+
+            update_fn(value)
+        };
+
+        self.value_changed(&key);
+
+        if key_op.is_none() && value_op.is_none() {
+            return None;
+        }
+
+        Some(ORMapOperation {
+            key: key,
+            key_op: key_op,
+            value_ops: value_op.into_iter().collect(),
+        })
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<ORMapOperation<K, V>> {
+        if !self.contains_key(&key) {
+            return None;
+        }
+
+        self.keys.remove(key.clone())
+            .map(|key_op| ORMapOperation { key: key, key_op: Some(key_op), value_ops: Vec::new() })
+    }
+
+    fn value_changed(&self, key: &K) {
+        self.digests.borrow_mut().changed.insert(key.clone());
+    }
+}
+
+// Changes made through an entry are staged on a copy of the value and only
+// reach the map in `finish`, so an entry dropped early leaves it untouched.
+impl<'m, K, V, S> Entry<'m, K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V>
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self) -> Option<&V> {
+        self.value.as_ref().or_else(|| self.map.get(&self.key))
+    }
+
+    pub fn or_insert(mut self) -> Self {
+        if self.key_op.is_none() && !self.map.contains_key(&self.key) {
+            self.key_op = Some(ORSetOperation::Add(self.key.clone(), Uuid::new_v4()));
+        }
+
+        self
+    }
+
+    pub fn update<F>(self, update_fn: F) -> Self
+        where F: FnOnce(&mut V) -> Option<V::Operation>,
+              V: Clone
+    {
+        let mut entry = self.or_insert();
+
+        if entry.value.is_none() {
+            let key = &entry.key;
+            let context = &entry.map.context;
+
+            entry.value = Some(entry.map.values.get(key).cloned().unwrap_or_else(|| V::create(key, context)));
+        }
+
+        if let Some(value_op) = update_fn(entry.value.as_mut().unwrap()) {
+            entry.value_ops.push(value_op);
+        }
+
+        entry
+    }
+
+    pub fn finish(self) -> Option<ORMapOperation<K, V>> {
+        if self.key_op.is_none() && self.value_ops.is_empty() {
+            return None;
+        }
+
+        let op = ORMapOperation {
+            key: self.key,
+            key_op: self.key_op,
+            value_ops: self.value_ops,
+        };
+
+        self.map.apply(&op);
+
+        Some(op)
+    }
+}
+
+impl<K1, K2, V, S1, S2> ObserveRemoveMap<K1, ObserveRemoveMap<K2, V, S2>, S1>
+    where K1: Hash + Eq + Clone,
+          K2: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K2>,
+          S1: MapStore<K1, ObserveRemoveMap<K2, V, S2>>,
+          S2: MapStore<K2, V> + Default
+{
+    pub fn get_in(&self, outer: &K1, inner: &K2) -> Option<&V> {
+        self.get(outer).and_then(|map| map.get(inner))
+    }
+
+    pub fn update_in<F>(&mut self, outer: K1, inner: K2, update_fn: F)
+        -> Option<ORMapOperation<K1, ObserveRemoveMap<K2, V, S2>>>
+        where F: FnOnce(&mut V) -> Option<V::Operation>
+    {
+        self.update(outer, |map| map.update(inner, update_fn))
+    }
+}
+
+impl<K, V, S> ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K> + Digest,
+          S: MapStore<K, V>
+{
+    // The key set and the values each keep a tree; the map's tree holds
+    // the items of both.
+    pub fn merkle(&self) -> MerkleTree {
+        self.update_digests();
+
+        let mut merkle = self.keys.merkle().clone();
+        merkle.toggle_tree(&self.digests.borrow().merkle);
+
+        merkle
+    }
+
+    pub fn delta(&self, buckets: &[MerklePath]) -> ObserveRemoveMap<K, V, S>
+        where V: Clone,
+              S: Default
+    {
+        self.update_digests();
+
+        let digests = self.digests.borrow();
+        let mut values = S::default();
+        let mut changed = HashSet::new();
+
+        for (key, value) in self.values.iter() {
+            if in_buckets(buckets, digests.items[key]) {
+                values.get_or_insert_with(key.clone(), || value.clone());
+                changed.insert(key.clone());
+            }
+        }
+
+        ObserveRemoveMap {
+            keys: self.keys.delta(buckets),
+            values: values,
+            context: self.context.clone(),
+            digests: RefCell::new(ValueDigests::new(changed)),
+        }
+    }
+
+    fn update_digests(&self) {
+        let mut digests = self.digests.borrow_mut();
+        let changed = mem::replace(&mut digests.changed, HashSet::new());
+
+        for key in changed {
+            if let Some(item) = digests.items.remove(&key) {
+                digests.merkle.toggle(item);
+            }
+
+            if let Some(value) = self.values.get(&key) {
+                let item = item_hash(&(&key, value.digest()));
+
+                digests.merkle.toggle(item);
+                digests.items.insert(key, item);
+            }
+        }
+    }
+}
+
+impl<J, K, V, S> ValueFactory<J> for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V> + Default
+{
+    type Context = V::Context;
+
+    fn create(_: &J, context: &V::Context) -> Self {
+        ObserveRemoveMap {
+            keys: ObserveRemoveSet::new(),
+            values: S::default(),
+            context: context.clone(),
+            digests: RefCell::new(ValueDigests::new(HashSet::new())),
+        }
+    }
+}
+
+impl<K, V, S> Encodable for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone + Encodable,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V> + Encodable
+{
+    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        ObserveRemoveMapState { keys: &self.keys, values: &self.values }.encode(e)
+    }
+}
+
+impl<K, V, S> Decodable for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone + Decodable,
+          V: OperationRDT + ValueFactory<K>,
+          V::Context: Default,
+          S: MapStore<K, V> + Decodable
+{
+    fn decode<D: Decoder>(d: &mut D) -> Result<ObserveRemoveMap<K, V, S>, D::Error> {
+        let state: ObserveRemoveMapState<ObserveRemoveSet<K>, S> = try!(Decodable::decode(d));
+        let changed = state.values.iter().map(|(key, _)| key.clone()).collect();
+
+        Ok(ObserveRemoveMap {
+            keys: state.keys,
+            values: state.values,
+            context: V::Context::default(),
+            digests: RefCell::new(ValueDigests::new(changed)),
+        })
+    }
+}
+
+impl<K, V, S> OperationRDT for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V>
+{
+    type Operation = ORMapOperation<K, V>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        if let Some(ref key_op) = op.key_op {
+            self.keys.apply(key_op);
+        }
+
+        let key_added = match op.key_op {
+            Some(ORSetOperation::Add(..)) => true,
+            _ => false,
+        };
+
+        if key_added || !op.value_ops.is_empty() {
+            let context = &self.context;
+            let key = &op.key;
+            let value = self.values.get_or_insert_with(key.clone(), || V::create(key, context));
+
+            for value_op in &op.value_ops {
+                value.apply(value_op);
+            }
+
+            self.value_changed(key);
+        }
+    }
+}
+
+impl<K, V, S> BatchRDT for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K>,
+          S: MapStore<K, V>
+{
+    type Batch = ORMapBatch<K, V>;
+
+    fn batch(ops: Vec<Self::Operation>) -> Option<Self::Batch> {
+        if ops.is_empty() {
+            return None;
+        }
+
+        let mut key_ops = Vec::new();
+        let mut values: Vec<(K, Vec<V::Operation>)> = Vec::new();
+        let mut positions: HashMap<K, usize> = HashMap::new();
+
+        for op in ops {
+            if let Some(key_op) = op.key_op {
+                key_ops.push(key_op);
+            }
+
+            if op.value_ops.is_empty() {
+                continue;
+            }
+
+            match positions.get(&op.key).cloned() {
+                Some(index) => values[index].1.extend(op.value_ops),
+                None => {
+                    positions.insert(op.key.clone(), values.len());
+                    values.push((op.key, op.value_ops));
+                },
+            }
+        }
+
+        Some(ORMapBatch {
+            keys: ObserveRemoveSet::batch(key_ops),
+            values: values,
+        })
+    }
+
+    fn apply_batch(&mut self, batch: &Self::Batch) {
+        if let Some(ref keys) = batch.keys {
+            self.keys.apply_batch(keys);
+
+            let context = &self.context;
+
+            for key in keys.added() {
+                self.values.get_or_insert_with(key.clone(), || V::create(key, context));
+                self.digests.get_mut().changed.insert(key.clone());
+            }
+        }
+
+        for &(ref key, ref value_ops) in &batch.values {
+            let context = &self.context;
+            let value = self.values.get_or_insert_with(key.clone(), || V::create(key, context));
+
+            for value_op in value_ops {
+                value.apply(value_op);
+            }
+
+            self.digests.get_mut().changed.insert(key.clone());
+        }
+    }
+}
+
+impl<K, V, S> StateRDT for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + StateRDT + ValueFactory<K>,
+          S: MapStore<K, V>
+{
+    fn merge(&mut self, other: &ObserveRemoveMap<K, V, S>) {
+        self.keys.merge(&other.keys);
+
+        let context = &self.context;
+
+        for (key, value) in other.values.iter() {
+            let my_value = self.values.get_or_insert_with(key.clone(), || V::create(key, context));
+            my_value.merge(value);
+
+            self.digests.get_mut().changed.insert(key.clone());
+        }
+    }
+}
+
+impl<K, V, S> DiffRDT for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + StateRDT + ValueFactory<K> + Digest + Clone,
+          S: MapStore<K, V> + Default
+{
+    type Summary = MerkleTree;
+    type Delta = ObserveRemoveMap<K, V, S>;
+
+    fn summary(&self) -> Self::Summary {
+        self.merkle()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.delta(&self.merkle().diff(summary))
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
+    }
+}
+
+impl<K, V, S> Digest for ObserveRemoveMap<K, V, S>
+    where K: Hash + Eq + Clone,
+          V: OperationRDT + ValueFactory<K> + Digest,
+          S: MapStore<K, V>
+{
+    fn digest(&self) -> u64 {
+        self.update_digests();
+
+        self.keys.merkle().root() ^ self.digests.borrow().merkle.root()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ObserveRemoveMap, OrderedObserveRemoveMap};
+
+    use sets::ObserveRemoveSet;
+
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    use rustc_serialize::json;
+
+    use core::{StateRDT, OperationRDT, BatchRDT, BatchBuilder, DiffRDT};
+    use bags::ORBag;
+    use counters::PNCounter;
+    use merkle::Digest;
+
+    type CounterMap = ObserveRemoveMap<&'static str, PNCounter<&'static str, i32>>;
+    type OrderedCounterMap<K> = OrderedObserveRemoveMap<K, PNCounter<&'static str, i32>>;
+
+    #[test]
+    fn make_counter_map() {
+        let m: CounterMap = ObserveRemoveMap::new("h1");
+
+        assert!(m.get(&"c1").is_none());
+    }
+
+    #[test]
+    fn encode_converged_ordered_maps_identically() {
+        let mut m1: OrderedObserveRemoveMap<u32, ObserveRemoveSet<u32>> = ObserveRemoveMap::new_ordered(());
+        let mut m2 = m1.clone();
+        let mut ops1 = Vec::new();
+        let mut ops2 = Vec::new();
+
+        for i in 0..16 {
+            ops1.push(m1.update(i, |s| Some(s.add(i))).unwrap());
+            ops2.push(m2.update(i, |s| Some(s.add(i + 1))).unwrap());
+        }
+
+        for i in 0..4 {
+            ops1.push(m1.remove(i).unwrap());
+        }
+
+        for op in &ops2 {
+            m1.apply(op);
+        }
+
+        for op in ops1.iter().rev() {
+            m2.apply(op);
+        }
+
+        assert_eq!(json::encode(&m1).unwrap(), json::encode(&m2).unwrap());
+
+        let decoded: OrderedObserveRemoveMap<u32, ObserveRemoveSet<u32>> = json::decode(&json::encode(&m1).unwrap()).unwrap();
+
+        assert_eq!(decoded.keys(), m1.keys());
+        assert_eq!(decoded.digest(), m1.digest());
+    }
+
+    #[test]
+    fn add_counter_map() {
+        let mut m: CounterMap = ObserveRemoveMap::new("h1");
+
+        m.update("c1", |mut c| c.add(5)).unwrap();
+        m.update("c2", |mut c| c.add(3)).unwrap();
+
+        assert_eq!(m.get(&"c1").unwrap().value(), 5);
+        assert_eq!(m.get(&"c2").unwrap().value(), 3);
+    }
+
+    #[test]
+    fn apply_counter_map_ops_independent() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        let op1 = m1.update("c1", |mut c| c.add(5)).unwrap();
+        let op2 = m2.update("c2", |mut c| c.add(3)).unwrap();
+
+        m2.apply(&op1);
+        m1.apply(&op2);
+
+        assert_eq!(m1.get(&"c1").unwrap().value(), 5);
+        assert_eq!(m1.get(&"c2").unwrap().value(), 3);
+
+        assert_eq!(m2.get(&"c1").unwrap().value(), 5);
+        assert_eq!(m2.get(&"c2").unwrap().value(), 3);
+    }
+
+    #[test]
+    fn apply_counter_map_ops_dependent() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        let op1 = m1.update("c1", |mut c| c.add(5)).unwrap();
+        let op2 = m2.update("c1", |mut c| c.add(3)).unwrap();
+
+        m2.apply(&op1);
+        m1.apply(&op2);
+
+        assert_eq!(m1.get(&"c1").unwrap().value(), 8);
+        assert_eq!(m2.get(&"c1").unwrap().value(), 8);
+
+        let op3 = m1.update("c1", |mut c| c.add(-4)).unwrap();
+
+        m2.apply(&op3);
+
+        assert_eq!(m1.get(&"c1").unwrap().value(), 4);
+        assert_eq!(m2.get(&"c1").unwrap().value(), 4);
+    }
+
+    #[test]
+    fn merge_counter_map_ops_dependent() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        m1.update("c1", |mut c| c.add(5)).unwrap();
+        m2.update("c1", |mut c| c.add(3)).unwrap();
+
+        m2.merge(&m1);
+        m1.merge(&m2);
+
+        assert_eq!(m1.get(&"c1").unwrap().value(), 8);
+        assert_eq!(m2.get(&"c1").unwrap().value(), 8);
+
+        m1.update("c1", |mut c| c.add(-4)).unwrap();
+
+        m2.merge(&m1);
+
+        assert_eq!(m1.get(&"c1").unwrap().value(), 4);
+        assert_eq!(m2.get(&"c1").unwrap().value(), 4);
+    }
+
+    #[test]
+    fn sync_counter_map_merkle() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        for key in &["c1", "c2", "c3"] {
+            m1.update(key, |c| c.add(1)).unwrap();
+        }
+
+        m2.merge(&m1);
+
+        assert_eq!(m1.merkle(), m2.merkle());
+
+        m2.update("c2", |c| c.add(4)).unwrap();
+
+        let buckets = m1.merkle().diff(&m2.merkle());
+        let delta = m2.delta(&buckets);
+
+        assert_eq!(delta.keys(), HashSet::new());
+
+        m1.merge(&delta);
+
+        assert_eq!(m1.get(&"c2").unwrap().value(), 5);
+        assert_eq!(m1.merkle(), m2.merkle());
+    }
+
+    #[test]
+    fn diff_counter_map_state() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        m1.update("c1", |c| c.add(1)).unwrap();
+        m2.merge(&m1);
+
+        m2.update("c2", |c| c.add(4)).unwrap();
+        m1.remove("c1").unwrap();
+
+        m1.apply_diff(&m2.diff(&m1.summary()));
+        m2.apply_diff(&m1.diff(&m2.summary()));
+
+        assert_eq!(m1.keys(), HashSet::from_iter(vec!["c2"]));
+        assert_eq!(m1.summary(), m2.summary());
+    }
+
+    #[test]
+    fn diff_bag_map_state() {
+        let mut m1: ObserveRemoveMap<&str, ORBag<&str>> = ObserveRemoveMap::new(());
+        let mut m2: ObserveRemoveMap<&str, ORBag<&str>> = ObserveRemoveMap::new(());
+
+        m1.update("b1", |b| b.add("x", 2)).unwrap();
+        m2.merge(&m1);
+
+        m2.update("b1", |b| b.add("y", 1)).unwrap();
+
+        m1.apply_diff(&m2.diff(&m1.summary()));
+
+        assert_eq!(m1.get(&"b1").unwrap().count(&"y"), 1);
+        assert_eq!(m1.summary(), m2.summary());
+    }
+
+    #[test]
+    fn remove_counter_map() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        let op1 = m1.update("c1", |c| c.add(5)).unwrap();
+        let op2 = m1.update("c2", |c| c.add(3)).unwrap();
+        let op3 = m1.remove("c1").unwrap();
+
+        m2.apply(&op1);
+        m2.apply(&op2);
+        m2.apply(&op3);
+
+        assert!(m1.remove("c1").is_none());
+        assert!(m2.get(&"c1").is_none());
+        assert_eq!(m2.keys(), HashSet::from_iter(vec!["c2"]));
+    }
+
+    #[test]
+    fn make_ordered_counter_map() {
+        let m: OrderedCounterMap<&str> = ObserveRemoveMap::new_ordered("h1");
+
+        assert!(m.first().is_none());
+        assert!(m.last().is_none());
+    }
+
+    #[test]
+    fn range_ordered_counter_map() {
+        let mut m: OrderedCounterMap<_> = ObserveRemoveMap::new_ordered("h1");
+
+        for (i, key) in ["d", "b", "a", "c", "e"].iter().enumerate() {
+            m.update(*key, |c| c.add(i as i32)).unwrap();
+        }
+
+        m.remove("e").unwrap();
+
+        let keys = m.range("b".."d").into_iter().map(|(k, _)| *k).collect::<Vec<_>>();
+
+        assert_eq!(keys, vec!["b", "c"]);
+        assert_eq!(m.first().map(|(k, _)| *k), Some("a"));
+        assert_eq!(m.last().map(|(k, _)| *k), Some("d"));
+    }
+
+    #[test]
+    fn merge_ordered_counter_map_iterates_deterministically() {
+        let mut m1: OrderedCounterMap<_> = ObserveRemoveMap::new_ordered("h1");
+        let mut m2: OrderedCounterMap<_> = ObserveRemoveMap::new_ordered("h2");
+
+        m1.update(3, |c| c.add(1)).unwrap();
+        m1.update(1, |c| c.add(1)).unwrap();
+        m2.update(2, |c| c.add(1)).unwrap();
+        m2.update(1, |c| c.add(1)).unwrap();
+
+        m1.merge(&m2);
+        m2.merge(&m1);
+
+        let entries1 = m1.iter().map(|(k, c)| (*k, c.value())).collect::<Vec<_>>();
+        let entries2 = m2.iter().map(|(k, c)| (*k, c.value())).collect::<Vec<_>>();
+
+        assert_eq!(entries1, vec![(1, 2), (2, 1), (3, 1)]);
+        assert_eq!(entries1, entries2);
+    }
+
+    #[test]
+    fn clone_counter_map() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+
+        m1.update("c1", |c| c.add(5)).unwrap();
+
+        let mut m2 = m1.clone();
+
+        m2.update("c2", |c| c.add(2)).unwrap();
+
+        assert_eq!(*m2.context(), "h1");
+        assert_eq!(m1.keys(), HashSet::from_iter(vec!["c1"]));
+        assert_eq!(m2.keys(), HashSet::from_iter(vec!["c1", "c2"]));
+    }
+
+    #[test]
+    fn apply_nested_counter_map_ops() {
+        type NestedMap = ObserveRemoveMap<&'static str, ObserveRemoveMap<&'static str, CounterMap>>;
+
+        let mut m1: NestedMap = ObserveRemoveMap::new("h1");
+        let mut m2: NestedMap = ObserveRemoveMap::new("h2");
+
+        let op1 = m1.update_in("eu", "fr", |m| m.update("paris", |c| c.add(5))).unwrap();
+        m2.apply(&op1);
+
+        let op2 = m1.update_in("eu", "fr", |m| m.update("lyon", |c| c.add(2))).unwrap();
+        let op3 = m2.update_in("eu", "fr", |m| m.update("paris", |c| c.add(3))).unwrap();
+        let op4 = m2.update_in("eu", "de", |m| m.update("berlin", |c| c.add(1))).unwrap();
+
+        m1.apply(&op3);
+        m1.apply(&op4);
+        m2.apply(&op2);
+
+        for m in &[&m1, &m2] {
+            assert_eq!(m.get_in(&"eu", &"fr").unwrap().get(&"paris").unwrap().value(), 8);
+            assert_eq!(m.get_in(&"eu", &"fr").unwrap().get(&"lyon").unwrap().value(), 2);
+            assert_eq!(m.get_in(&"eu", &"de").unwrap().get(&"berlin").unwrap().value(), 1);
+        }
+
+        assert_eq!(*m2.get_in(&"eu", &"fr").unwrap().context(), "h2");
+    }
+
+    #[test]
+    fn merge_nested_counter_map_state() {
+        type NestedMap = ObserveRemoveMap<&'static str, ObserveRemoveMap<&'static str, CounterMap>>;
+
+        let mut m1: NestedMap = ObserveRemoveMap::new("h1");
+        let mut m2: NestedMap = ObserveRemoveMap::new("h2");
+
+        m1.update_in("eu", "fr", |m| m.update("paris", |c| c.add(5))).unwrap();
+        m1.update_in("us", "ca", |m| m.update("la", |c| c.add(4))).unwrap();
+        m2.merge(&m1);
+
+        m1.update_in("eu", "fr", |m| m.remove("paris")).unwrap();
+        m2.update_in("us", "ca", |m| m.update("sf", |c| c.add(7))).unwrap();
+
+        m1.merge(&m2);
+        m2.merge(&m1);
+
+        for m in &[&m1, &m2] {
+            assert!(m.get_in(&"eu", &"fr").unwrap().get(&"paris").is_none());
+            assert_eq!(m.get_in(&"us", &"ca").unwrap().keys(), HashSet::from_iter(vec!["la", "sf"]));
+        }
+    }
+
+    #[test]
+    fn insert_key_only_entry() {
+        let mut m1: CounterMap = ObserveRemoveMap::new("h1");
+        let mut m2: CounterMap = ObserveRemoveMap::new("h2");
+
+        let op1 = m1.update("c1", |_| None).unwrap();
+        let op2 = m1.entry("c2").or_insert().finish().unwrap();
+
+        m2.apply(&op1);
+        m2.apply(&op2);
+
+        assert!(m1.entry("c2").or_insert().finish().is_none());
+        assert!(m1.update("c1", |_| None).is_none());
+        assert_eq!(m2.get(&"c1").unwrap().value(), 0);
+        assert_eq!(m2.keys(), HashSet::from_iter(vec!["c1", "c2"]));
+    }
+
+    #[test]
+    fn apply_entry_with_multiple_value_ops() {
+        let mut m1: ObserveRemoveMap<&str, ObserveRemoveSet<&str>> = ObserveRemoveMap::new(());
+        let mut m2: ObserveRemoveMap<&str, ObserveRemoveSet<&str>> = ObserveRemoveMap::new(());
+
+        let op = m1.entry("alice")
+            .update(|tags| Some(tags.add("admin")))
+            .update(|tags| Some(tags.add("dev")))
+            .update(|tags| tags.remove("admin"))
+            .finish()
+            .unwrap();
+
+        m2.apply(&op);
+
+        assert_eq!(m1.get(&"alice").unwrap().value(), HashSet::from_iter(vec!["dev"]));
+        assert_eq!(m2.get(&"alice").unwrap().value(), HashSet::from_iter(vec!["dev"]));
+    }
+
+    #[test]
+    fn drop_unfinished_entry() {
+        let mut m1: ObserveRemoveMap<&str, ObserveRemoveSet<&str>> = ObserveRemoveMap::new(());
+
+        m1.update("alice", |tags| Some(tags.add("admin"))).unwrap();
+
+        {
+            let entry = m1.entry("alice")
+                .update(|tags| tags.remove("admin"))
+                .update(|tags| Some(tags.add("dev")));
+
+            assert_eq!(entry.get().unwrap().value(), HashSet::from_iter(vec!["dev"]));
+        }
+
+        {
+            let entry = m1.entry("bob").update(|tags| Some(tags.add("dev")));
+
+            assert!(entry.get().is_some());
+        }
+
+        assert_eq!(m1.get(&"alice").unwrap().value(), HashSet::from_iter(vec!["admin"]));
+        assert!(!m1.contains_key(&"bob"));
+    }
+
+    #[test]
+    fn apply_counter_map_batch() {
+        let mut m1: ObserveRemoveMap<u32, PNCounter<&str, i32>> = ObserveRemoveMap::new("h1");
+        let mut m2: ObserveRemoveMap<u32, PNCounter<&str, i32>> = ObserveRemoveMap::new("h2");
+
+        m1.update(0, |c| c.add(1)).unwrap();
+        m2.merge(&m1);
+
+        let batch = {
+            let mut builder = BatchBuilder::new(&mut m1);
+
+            for i in 1..1000 {
+                builder.update(|m| m.update(i, |c| c.add(i as i32)));
+            }
+
+            builder.update(|m| m.update(5, |c| c.add(-5)));
+            builder.update(|m| m.remove(0));
+            builder.update(|m| m.remove(7));
+
+            builder.commit().unwrap()
+        };
+
+        m2.apply_batch(&batch);
+
+        assert_eq!(m2.keys().len(), 998);
+        assert_eq!(m2.keys(), m1.keys());
+        assert_eq!(m2.get(&5).unwrap().value(), 0);
+        assert_eq!(m2.get(&999).unwrap().value(), 999);
+        assert!(m2.get(&0).is_none());
+    }
+}
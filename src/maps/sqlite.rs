@@ -0,0 +1,710 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::hash::Hash;
+use std::path::Path;
+
+use rusqlite::{self, Connection, OptionalExtension};
+use rustc_serialize::{Encodable, Decodable};
+use rustc_serialize::json;
+use uuid::Uuid;
+
+use core::{StateRDT, OperationRDT, ValueFactory};
+use merkle::{MerkleTree, Digest, item_hash, in_buckets};
+use sets::ORSetOperation;
+use storage::StorageError;
+
+use super::ORMapOperation;
+
+const SCHEMA: &'static str = "
+    CREATE TABLE IF NOT EXISTS map_tags (
+        key TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (key, tag)
+    );
+    CREATE TABLE IF NOT EXISTS map_tombstones (
+        tag TEXT PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS map_values (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        digest INTEGER NOT NULL
+    );
+";
+
+// Keys, tags and values are stored JSON encoded, each value next to its
+// digest. Every change is written through to the database, so the cache
+// only holds recently used values and can be dropped at any time.
+pub struct SqliteObserveRemoveMap<K, V>
+    where K: Hash + Eq + Clone + Encodable + Decodable,
+          V: OperationRDT + ValueFactory<K> + Digest + Encodable + Decodable
+{
+    conn: Connection,
+    context: V::Context,
+    cache: RefCell<Cache<K, V>>,
+}
+
+// Rows are shipped as they are stored, JSON encoded.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct SqliteMapDelta {
+    tags: Vec<(String, String)>,
+    tombstones: Vec<String>,
+    values: Vec<(String, String)>,
+}
+
+// Every use stamps a key with the next tick; the least recently used key
+// is first in `order`.
+struct Cache<K, V> {
+    values: HashMap<K, V>,
+    ticks: HashMap<K, u64>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    size: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> {
+    fn touch(&mut self, key: &K) {
+        if let Some(old) = self.ticks.remove(key) {
+            self.order.remove(&old);
+        }
+
+        self.tick += 1;
+        self.ticks.insert(key.clone(), self.tick);
+        self.order.insert(self.tick, key.clone());
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        while self.values.len() >= self.size {
+            let oldest = match self.order.keys().next() {
+                Some(&tick) => tick,
+                None => break,
+            };
+
+            if let Some(old) = self.order.remove(&oldest) {
+                self.ticks.remove(&old);
+                self.values.remove(&old);
+            }
+        }
+
+        self.touch(&key);
+        self.values.insert(key, value);
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.ticks.clear();
+        self.order.clear();
+    }
+}
+
+impl<K, V> SqliteObserveRemoveMap<K, V>
+    where K: Hash + Eq + Clone + Encodable + Decodable,
+          V: OperationRDT + ValueFactory<K> + Digest + Encodable + Decodable
+{
+    pub fn open<P: AsRef<Path>>(path: P, context: V::Context, cache_size: usize)
+        -> Result<SqliteObserveRemoveMap<K, V>, StorageError>
+    {
+        let conn = try!(Connection::open(path));
+
+        SqliteObserveRemoveMap::with_connection(conn, context, cache_size)
+    }
+
+    pub fn open_in_memory(context: V::Context, cache_size: usize)
+        -> Result<SqliteObserveRemoveMap<K, V>, StorageError>
+    {
+        let conn = try!(Connection::open_in_memory());
+
+        SqliteObserveRemoveMap::with_connection(conn, context, cache_size)
+    }
+
+    fn with_connection(conn: Connection, context: V::Context, cache_size: usize)
+        -> Result<SqliteObserveRemoveMap<K, V>, StorageError>
+    {
+        try!(conn.execute_batch(SCHEMA));
+
+        Ok(SqliteObserveRemoveMap {
+            conn: conn,
+            context: context,
+            cache: RefCell::new(Cache {
+                values: HashMap::new(),
+                ticks: HashMap::new(),
+                order: BTreeMap::new(),
+                tick: 0,
+                size: cache_size,
+            }),
+        })
+    }
+
+    pub fn context<'a>(&'a self) -> &'a V::Context {
+        &self.context
+    }
+
+    pub fn contains_key(&self, key: &K) -> Result<bool, StorageError> {
+        let key = try!(json::encode(key));
+
+        let found = try!(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM map_tags
+                WHERE key = ?1 AND tag NOT IN (SELECT tag FROM map_tombstones))",
+            rusqlite::params![key],
+            |row| row.get(0)));
+
+        Ok(found)
+    }
+
+    pub fn len(&self) -> Result<usize, StorageError> {
+        let count: i64 = try!(self.conn.query_row(
+            "SELECT COUNT(DISTINCT key) FROM map_tags
+                WHERE tag NOT IN (SELECT tag FROM map_tombstones)",
+            [],
+            |row| row.get(0)));
+
+        Ok(count as usize)
+    }
+
+    pub fn keys(&self) -> Result<HashSet<K>, StorageError> {
+        let mut stmt = try!(self.conn.prepare(
+            "SELECT DISTINCT key FROM map_tags
+                WHERE tag NOT IN (SELECT tag FROM map_tombstones)"));
+        let mut rows = try!(stmt.query([]));
+        let mut keys = HashSet::new();
+
+        while let Some(row) = try!(rows.next()) {
+            let key: String = try!(row.get(0));
+            keys.insert(try!(json::decode(&key)));
+        }
+
+        Ok(keys)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, StorageError>
+        where V: Clone
+    {
+        if !try!(self.contains_key(key)) {
+            return Ok(None);
+        }
+
+        try!(self.load(key));
+
+        Ok(Some(self.cache.borrow().values[key].clone()))
+    }
+
+    pub fn update<F>(&mut self, key: K, update_fn: F)
+        -> Result<Option<ORMapOperation<K, V>>, StorageError>
+        where F: FnOnce(&mut V) -> Option<V::Operation>
+    {
+        self.transaction(move |map| {
+            let key_op = if try!(map.contains_key(&key)) {
+                None
+            }
+            else {
+                let key_op = ORSetOperation::Add(key.clone(), Uuid::new_v4());
+                try!(map.apply_key_op(&key_op));
+                Some(key_op)
+            };
+
+            let value_op = update_fn(try!(map.load_mut(&key)));
+            try!(map.store(&key));
+
+            if key_op.is_none() && value_op.is_none() {
+                return Ok(None);
+            }
+
+            Ok(Some(ORMapOperation {
+                key: key,
+                key_op: key_op,
+                value_ops: value_op.into_iter().collect(),
+            }))
+        })
+    }
+
+    pub fn remove(&mut self, key: K) -> Result<Option<ORMapOperation<K, V>>, StorageError> {
+        self.transaction(move |map| {
+            if !try!(map.contains_key(&key)) {
+                return Ok(None);
+            }
+
+            let tags = {
+                let encoded = try!(json::encode(&key));
+                let mut stmt = try!(map.conn.prepare("SELECT tag FROM map_tags WHERE key = ?1"));
+                let mut rows = try!(stmt.query(rusqlite::params![encoded]));
+                let mut tags = HashSet::new();
+
+                while let Some(row) = try!(rows.next()) {
+                    let tag: String = try!(row.get(0));
+                    tags.insert(try!(json::decode(&tag)));
+                }
+
+                tags
+            };
+
+            let key_op = ORSetOperation::Remove(tags);
+            try!(map.apply_key_op(&key_op));
+
+            Ok(Some(ORMapOperation { key: key, key_op: Some(key_op), value_ops: Vec::new() }))
+        })
+    }
+
+    pub fn apply(&mut self, op: &ORMapOperation<K, V>) -> Result<(), StorageError> {
+        self.transaction(|map| {
+            if let Some(ref key_op) = op.key_op {
+                try!(map.apply_key_op(key_op));
+            }
+
+            let key_added = match op.key_op {
+                Some(ORSetOperation::Add(..)) => true,
+                _ => false,
+            };
+
+            if key_added || !op.value_ops.is_empty() {
+                {
+                    let value = try!(map.load_mut(&op.key));
+
+                    for value_op in &op.value_ops {
+                        value.apply(value_op);
+                    }
+                }
+
+                try!(map.store(&op.key));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn apply_key_op(&self, op: &ORSetOperation<K>) -> Result<(), StorageError> {
+        match op {
+            &ORSetOperation::Add(ref key, ref tag) => {
+                try!(self.conn.execute(
+                    "INSERT OR IGNORE INTO map_tags (key, tag) VALUES (?1, ?2)",
+                    rusqlite::params![try!(json::encode(key)), try!(json::encode(tag))]));
+            },
+            &ORSetOperation::Remove(ref tags) => {
+                for tag in tags {
+                    try!(self.conn.execute(
+                        "INSERT OR IGNORE INTO map_tombstones (tag) VALUES (?1)",
+                        rusqlite::params![try!(json::encode(tag))]));
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn stored_value(&self, key: &K) -> Result<Option<String>, StorageError> {
+        let stored = try!(self.conn.query_row(
+            "SELECT value FROM map_values WHERE key = ?1",
+            rusqlite::params![try!(json::encode(key))],
+            |row| row.get(0)).optional());
+
+        Ok(stored)
+    }
+
+    // Loads a value into the cache, evicting the least recently used values
+    // to make room for it.
+    fn load(&self, key: &K) -> Result<(), StorageError> {
+        if self.cache.borrow().values.contains_key(key) {
+            self.cache.borrow_mut().touch(key);
+
+            return Ok(());
+        }
+
+        let value = match try!(self.stored_value(key)) {
+            Some(stored) => try!(json::decode(&stored)),
+            None => V::create(key, &self.context),
+        };
+
+        self.cache.borrow_mut().insert(key.clone(), value);
+
+        Ok(())
+    }
+
+    fn load_mut(&mut self, key: &K) -> Result<&mut V, StorageError> {
+        try!(self.load(key));
+
+        Ok(self.cache.get_mut().values.get_mut(key).unwrap())
+    }
+
+    fn store(&self, key: &K) -> Result<(), StorageError> {
+        let (value, digest) = {
+            let cache = self.cache.borrow();
+            let value = &cache.values[key];
+
+            (try!(json::encode(value)), value.digest() as i64)
+        };
+
+        try!(self.conn.execute(
+            "INSERT OR REPLACE INTO map_values (key, value, digest) VALUES (?1, ?2, ?3)",
+            rusqlite::params![try!(json::encode(key)), value, digest]));
+
+        Ok(())
+    }
+
+    // A failed write rolls the database back, so the cache, which may
+    // already hold the changed values, is dropped with it.
+    fn transaction<T, F>(&mut self, body: F) -> Result<T, StorageError>
+        where F: FnOnce(&mut Self) -> Result<T, StorageError>
+    {
+        try!(self.conn.execute_batch("BEGIN"));
+
+        let result = body(self)
+            .and_then(|result| self.conn.execute_batch("COMMIT").map(|_| result).map_err(From::from));
+
+        if result.is_err() {
+            let _ = self.conn.execute_batch("ROLLBACK");
+
+            self.cache.get_mut().clear();
+        }
+
+        result
+    }
+}
+
+impl<K, V> SqliteObserveRemoveMap<K, V>
+    where K: Hash + Eq + Clone + Encodable + Decodable,
+          V: OperationRDT + StateRDT + ValueFactory<K> + Digest + Encodable + Decodable
+{
+    pub fn merge(&mut self, other: &SqliteObserveRemoveMap<K, V>) -> Result<(), StorageError> {
+        self.transaction(|map| {
+            {
+                let mut stmt = try!(other.conn.prepare("SELECT key, tag FROM map_tags"));
+                let mut rows = try!(stmt.query([]));
+
+                while let Some(row) = try!(rows.next()) {
+                    let (key, tag): (String, String) = (try!(row.get(0)), try!(row.get(1)));
+
+                    try!(map.insert_tag(&key, &tag));
+                }
+            }
+
+            {
+                let mut stmt = try!(other.conn.prepare("SELECT tag FROM map_tombstones"));
+                let mut rows = try!(stmt.query([]));
+
+                while let Some(row) = try!(rows.next()) {
+                    let tag: String = try!(row.get(0));
+
+                    try!(map.insert_tombstone(&tag));
+                }
+            }
+
+            let mut stmt = try!(other.conn.prepare("SELECT key, value FROM map_values"));
+            let mut rows = try!(stmt.query([]));
+
+            while let Some(row) = try!(rows.next()) {
+                let (key, value): (String, String) = (try!(row.get(0)), try!(row.get(1)));
+
+                try!(map.merge_value(&key, &value));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn insert_tag(&self, key: &str, tag: &str) -> Result<(), StorageError> {
+        try!(self.conn.execute(
+            "INSERT OR IGNORE INTO map_tags (key, tag) VALUES (?1, ?2)",
+            rusqlite::params![key, tag]));
+
+        Ok(())
+    }
+
+    fn insert_tombstone(&self, tag: &str) -> Result<(), StorageError> {
+        try!(self.conn.execute(
+            "INSERT OR IGNORE INTO map_tombstones (tag) VALUES (?1)",
+            rusqlite::params![tag]));
+
+        Ok(())
+    }
+
+    // Values stored identically on both sides are already merged, so only
+    // the rows that differ are decoded.
+    fn merge_value(&mut self, key: &str, value: &str) -> Result<(), StorageError> {
+        let key: K = try!(json::decode(key));
+
+        if try!(self.stored_value(&key)).map_or(false, |stored| stored == value) {
+            return Ok(());
+        }
+
+        let value: V = try!(json::decode(value));
+
+        try!(self.load_mut(&key)).merge(&value);
+        self.store(&key)
+    }
+}
+
+// The SQLite map can't implement DiffRDT, since every step reads from or
+// writes to the database and can fail. These methods follow the trait.
+// Tags and tombstones are items of the summary tree as stored; values are
+// folded in as (key, digest) items from the stored digests, so equal values
+// match even when their encodings differ and no value is decoded.
+impl<K, V> SqliteObserveRemoveMap<K, V>
+    where K: Hash + Eq + Clone + Encodable + Decodable,
+          V: OperationRDT + StateRDT + ValueFactory<K> + Digest + Encodable + Decodable
+{
+    pub fn summary(&self) -> Result<MerkleTree, StorageError> {
+        let (rows, digests) = try!(self.rows());
+
+        Ok(row_hashes(&rows, &digests).into_iter().collect())
+    }
+
+    pub fn diff(&self, summary: &MerkleTree) -> Result<SqliteMapDelta, StorageError> {
+        let (rows, digests) = try!(self.rows());
+        let hashes = row_hashes(&rows, &digests);
+        let buckets = hashes.iter().cloned().collect::<MerkleTree>().diff(summary);
+
+        let mut differs = hashes.into_iter().map(|hash| in_buckets(&buckets, hash));
+
+        Ok(SqliteMapDelta {
+            tags: differing(rows.tags, &mut differs),
+            tombstones: differing(rows.tombstones, &mut differs),
+            values: differing(rows.values, &mut differs),
+        })
+    }
+
+    pub fn apply_diff(&mut self, delta: &SqliteMapDelta) -> Result<(), StorageError> {
+        self.transaction(|map| {
+            for &(ref key, ref tag) in &delta.tags {
+                try!(map.insert_tag(key, tag));
+            }
+
+            for tag in &delta.tombstones {
+                try!(map.insert_tombstone(tag));
+            }
+
+            for &(ref key, ref value) in &delta.values {
+                try!(map.merge_value(key, value));
+            }
+
+            Ok(())
+        })
+    }
+
+    // Every row, in the shape of a delta, with the digest of each value.
+    fn rows(&self) -> Result<(SqliteMapDelta, Vec<u64>), StorageError> {
+        let mut rows = SqliteMapDelta { tags: Vec::new(), tombstones: Vec::new(), values: Vec::new() };
+        let mut digests = Vec::new();
+
+        {
+            let mut stmt = try!(self.conn.prepare("SELECT key, tag FROM map_tags"));
+            let mut result = try!(stmt.query([]));
+
+            while let Some(row) = try!(result.next()) {
+                rows.tags.push((try!(row.get(0)), try!(row.get(1))));
+            }
+        }
+
+        {
+            let mut stmt = try!(self.conn.prepare("SELECT tag FROM map_tombstones"));
+            let mut result = try!(stmt.query([]));
+
+            while let Some(row) = try!(result.next()) {
+                rows.tombstones.push(try!(row.get(0)));
+            }
+        }
+
+        let mut stmt = try!(self.conn.prepare("SELECT key, value, digest FROM map_values"));
+        let mut result = try!(stmt.query([]));
+
+        while let Some(row) = try!(result.next()) {
+            let digest: i64 = try!(row.get(2));
+
+            rows.values.push((try!(row.get(0)), try!(row.get(1))));
+            digests.push(digest as u64);
+        }
+
+        Ok((rows, digests))
+    }
+}
+
+// One hash per row, in the order the rows are listed.
+fn row_hashes(rows: &SqliteMapDelta, digests: &[u64]) -> Vec<u64> {
+    let tags = rows.tags.iter().map(item_hash);
+    let tombstones = rows.tombstones.iter().map(|tag| item_hash(&(tag, true)));
+    let values = rows.values.iter().zip(digests).map(|(&(ref key, _), digest)| item_hash(&(key, digest)));
+
+    tags.chain(tombstones).chain(values).collect()
+}
+
+fn differing<T, I: Iterator<Item=bool>>(rows: Vec<T>, differs: &mut I) -> Vec<T> {
+    rows.into_iter().zip(differs).filter(|&(_, differs)| differs).map(|(row, _)| row).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::SqliteObserveRemoveMap;
+
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs;
+    use std::iter::FromIterator;
+
+    use uuid::Uuid;
+
+    use maps::ObserveRemoveMap;
+    use core::OperationRDT;
+    use counters::PNCounter;
+
+    type CounterMap = ObserveRemoveMap<String, PNCounter<String, i64>>;
+    type SqliteCounterMap = SqliteObserveRemoveMap<String, PNCounter<String, i64>>;
+
+    fn key(k: &str) -> String {
+        k.to_owned()
+    }
+
+    #[test]
+    fn make_sqlite_map() {
+        let m: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 10).unwrap();
+
+        assert!(m.get(&key("c1")).unwrap().is_none());
+        assert_eq!(m.len().unwrap(), 0);
+    }
+
+    #[test]
+    fn update_sqlite_map() {
+        let mut m: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 10).unwrap();
+
+        m.update(key("c1"), |c| c.add(5)).unwrap().unwrap();
+        m.update(key("c2"), |c| c.add(3)).unwrap().unwrap();
+        m.remove(key("c2")).unwrap().unwrap();
+
+        assert!(m.remove(key("c3")).unwrap().is_none());
+        assert_eq!(m.get(&key("c1")).unwrap().unwrap().value(), 5);
+        assert!(m.get(&key("c2")).unwrap().is_none());
+        assert_eq!(m.keys().unwrap(), HashSet::from_iter(vec![key("c1")]));
+    }
+
+    #[test]
+    fn apply_sqlite_map_operations() {
+        let mut m1: CounterMap = ObserveRemoveMap::new(key("h1"));
+        let mut m2: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h2"), 10).unwrap();
+
+        let op1 = m1.update(key("c1"), |c| c.add(5)).unwrap();
+        m2.apply(&op1).unwrap();
+
+        let op2 = m2.update(key("c1"), |c| c.add(2)).unwrap().unwrap();
+        let op3 = m2.update(key("c2"), |c| c.add(1)).unwrap().unwrap();
+        let op4 = m2.remove(key("c2")).unwrap().unwrap();
+
+        for op in &[op2, op3, op4] {
+            m1.apply(op);
+        }
+
+        assert_eq!(m1.get(&key("c1")).unwrap().value(), 7);
+        assert_eq!(m2.get(&key("c1")).unwrap().unwrap().value(), 7);
+        assert_eq!(m1.keys(), m2.keys().unwrap());
+    }
+
+    #[test]
+    fn evict_sqlite_map_cache() {
+        let mut m: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 2).unwrap();
+
+        for i in 0..5 {
+            m.update(format!("c{}", i), |c| c.add(i)).unwrap();
+        }
+
+        assert_eq!(m.cache.borrow().values.len(), 2);
+
+        for i in 0..5 {
+            assert_eq!(m.get(&format!("c{}", i)).unwrap().unwrap().value(), i);
+        }
+
+        assert_eq!(m.cache.borrow().values.len(), 2);
+    }
+
+    #[test]
+    fn keep_recently_used_values_cached() {
+        let mut m: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 2).unwrap();
+
+        m.update(key("c1"), |c| c.add(1)).unwrap();
+        m.update(key("c2"), |c| c.add(2)).unwrap();
+        m.get(&key("c1")).unwrap();
+        m.update(key("c3"), |c| c.add(3)).unwrap();
+
+        let cache = m.cache.borrow();
+
+        assert!(cache.values.contains_key(&key("c1")));
+        assert!(!cache.values.contains_key(&key("c2")));
+        assert!(cache.values.contains_key(&key("c3")));
+    }
+
+    #[test]
+    fn merge_sqlite_maps() {
+        let mut m1: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 10).unwrap();
+        let mut m2: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h2"), 10).unwrap();
+
+        m1.update(key("c1"), |c| c.add(5)).unwrap();
+        m1.update(key("c2"), |c| c.add(1)).unwrap();
+        m2.merge(&m1).unwrap();
+
+        m1.remove(key("c2")).unwrap();
+        m2.update(key("c1"), |c| c.add(3)).unwrap();
+        m2.update(key("c3"), |c| c.add(2)).unwrap();
+
+        m1.merge(&m2).unwrap();
+
+        assert_eq!(m1.get(&key("c1")).unwrap().unwrap().value(), 8);
+        assert_eq!(m1.keys().unwrap(), HashSet::from_iter(vec![key("c1"), key("c3")]));
+    }
+
+    #[test]
+    fn diff_sqlite_maps() {
+        let mut m1: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 10).unwrap();
+        let mut m2: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h2"), 10).unwrap();
+
+        for i in 0..50 {
+            m1.update(format!("c{}", i), |c| c.add(i)).unwrap();
+        }
+
+        m2.merge(&m1).unwrap();
+
+        assert!(m1.diff(&m2.summary().unwrap()).unwrap().values.is_empty());
+
+        m1.remove(key("c1")).unwrap();
+        m2.update(key("c2"), |c| c.add(1)).unwrap();
+
+        let delta = m2.diff(&m1.summary().unwrap()).unwrap();
+
+        assert!(delta.values.len() < 10);
+
+        m1.apply_diff(&delta).unwrap();
+        m2.apply_diff(&m1.diff(&m2.summary().unwrap()).unwrap()).unwrap();
+
+        assert_eq!(m1.get(&key("c2")).unwrap().unwrap().value(), 3);
+        assert!(m2.get(&key("c1")).unwrap().is_none());
+        assert_eq!(m1.keys().unwrap(), m2.keys().unwrap());
+        assert_eq!(m1.summary().unwrap(), m2.summary().unwrap());
+    }
+
+    #[test]
+    fn summarize_sqlite_map_from_stored_digests() {
+        let mut m: SqliteCounterMap = SqliteObserveRemoveMap::open_in_memory(key("h1"), 10).unwrap();
+
+        m.update(key("c1"), |c| c.add(5)).unwrap();
+
+        let summary = m.summary().unwrap();
+
+        m.conn.execute("UPDATE map_values SET value = 'not json'", []).unwrap();
+
+        assert_eq!(m.summary().unwrap(), summary);
+        assert!(m.diff(&summary).unwrap().values.is_empty());
+    }
+
+    #[test]
+    fn reopen_sqlite_map() {
+        let path = env::temp_dir().join(format!("crdt-map-{}.sqlite", Uuid::new_v4()));
+
+        {
+            let mut m: SqliteCounterMap = SqliteObserveRemoveMap::open(&path, key("h1"), 10).unwrap();
+
+            m.update(key("c1"), |c| c.add(5)).unwrap();
+            m.update(key("c2"), |c| c.add(3)).unwrap();
+            m.remove(key("c1")).unwrap();
+        }
+
+        let m: SqliteCounterMap = SqliteObserveRemoveMap::open(&path, key("h1"), 10).unwrap();
+
+        assert!(m.get(&key("c1")).unwrap().is_none());
+        assert_eq!(m.get(&key("c2")).unwrap().unwrap().value(), 3);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
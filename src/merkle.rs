@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::iter::FromIterator;
+
+use hashing::stable_hash;
+
+// Leaves sit four levels below the root, one per value of the top 16 bits
+// of an item hash.
+const FANOUT: u32 = 16;
+const DEPTH: u8 = 4;
+
+pub trait Digest {
+    fn digest(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, RustcEncodable, RustcDecodable)]
+pub struct MerklePath {
+    depth: u8,
+    prefix: u16,
+}
+
+// Every node holds the XOR of the hashes of all items below it, so adding
+// or removing an item toggles it along a single path. Only non-zero nodes
+// are stored.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct MerkleTree {
+    nodes: HashMap<u32, u64>,
+}
+
+// Item hashes are compared between replicas, so they use the stable hash.
+pub fn item_hash<T: Hash>(item: &T) -> u64 {
+    stable_hash(item)
+}
+
+pub fn in_buckets(buckets: &[MerklePath], hash: u64) -> bool {
+    buckets.iter().any(|bucket| bucket.contains(hash))
+}
+
+impl MerklePath {
+    pub fn root() -> MerklePath {
+        MerklePath { depth: 0, prefix: 0 }
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.depth == DEPTH
+    }
+
+    pub fn children(&self) -> Vec<MerklePath> {
+        if self.is_leaf() {
+            return Vec::new();
+        }
+
+        (0..FANOUT as u16)
+            .map(|i| MerklePath { depth: self.depth + 1, prefix: self.prefix * FANOUT as u16 + i })
+            .collect()
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        MerklePath::leaf(hash).ancestor(self.depth) == *self
+    }
+
+    fn leaf(hash: u64) -> MerklePath {
+        MerklePath { depth: DEPTH, prefix: (hash >> 48) as u16 }
+    }
+
+    fn ancestor(&self, depth: u8) -> MerklePath {
+        MerklePath {
+            depth: depth,
+            prefix: (self.prefix as u32 >> (4 * (self.depth - depth) as u32)) as u16,
+        }
+    }
+
+    // Nodes are numbered level by level, starting with the root at zero.
+    fn index(&self) -> u32 {
+        (FANOUT.pow(self.depth as u32) - 1) / (FANOUT - 1) + self.prefix as u32
+    }
+}
+
+impl MerkleTree {
+    pub fn new() -> MerkleTree {
+        MerkleTree {
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> u64 {
+        self.hash(&MerklePath::root())
+    }
+
+    pub fn hash(&self, path: &MerklePath) -> u64 {
+        self.nodes.get(&path.index()).cloned().unwrap_or(0)
+    }
+
+    pub fn children(&self, path: &MerklePath) -> Vec<u64> {
+        path.children().iter().map(|child| self.hash(child)).collect()
+    }
+
+    // Given a peer's child hashes below `path`, returns the children whose
+    // subtrees differ and have to be descended into.
+    pub fn compare(&self, path: &MerklePath, children: &[u64]) -> Vec<MerklePath> {
+        path.children().into_iter()
+            .zip(children.iter())
+            .filter(|&(ref child, hash)| self.hash(child) != *hash)
+            .map(|(child, _)| child)
+            .collect()
+    }
+
+    pub fn diff(&self, other: &MerkleTree) -> Vec<MerklePath> {
+        let mut pending = vec![MerklePath::root()];
+        let mut buckets = Vec::new();
+
+        while let Some(path) = pending.pop() {
+            if path.is_leaf() {
+                buckets.push(path);
+            }
+            else {
+                pending.extend(self.compare(&path, &other.children(&path)));
+            }
+        }
+
+        buckets.sort();
+
+        buckets
+    }
+
+    pub fn toggle(&mut self, hash: u64) {
+        let leaf = MerklePath::leaf(hash);
+
+        for depth in 0..DEPTH + 1 {
+            let path = leaf.ancestor(depth);
+            let node = self.nodes.entry(path.index()).or_insert(0);
+            *node ^= hash;
+
+            if *node == 0 {
+                self.nodes.remove(&path.index());
+            }
+        }
+    }
+
+    // Toggles every item of `other`, the same as toggling them one by one.
+    pub fn toggle_tree(&mut self, other: &MerkleTree) {
+        for (&index, &hash) in &other.nodes {
+            let node = self.nodes.entry(index).or_insert(0);
+            *node ^= hash;
+
+            if *node == 0 {
+                self.nodes.remove(&index);
+            }
+        }
+    }
+}
+
+impl FromIterator<u64> for MerkleTree {
+    fn from_iter<I: IntoIterator<Item=u64>>(hashes: I) -> MerkleTree {
+        let mut merkle = MerkleTree::new();
+
+        for hash in hashes {
+            merkle.toggle(hash);
+        }
+
+        merkle
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MerkleTree, MerklePath, item_hash};
+
+    #[test]
+    fn make_merkle_tree() {
+        let tree = MerkleTree::new();
+
+        assert_eq!(tree.root(), 0);
+        assert_eq!(tree.children(&MerklePath::root()), vec![0; 16]);
+    }
+
+    #[test]
+    fn toggle_merkle_tree() {
+        let mut t1 = MerkleTree::new();
+        let mut t2 = MerkleTree::new();
+
+        for i in 0..100 {
+            t1.toggle(item_hash(&i));
+        }
+
+        for i in (0..100).rev() {
+            t2.toggle(item_hash(&i));
+        }
+
+        assert!(t1.root() != 0);
+        assert_eq!(t1, t2);
+
+        for i in 0..100 {
+            t1.toggle(item_hash(&i));
+        }
+
+        assert_eq!(t1, MerkleTree::new());
+    }
+
+    #[test]
+    fn diff_merkle_trees() {
+        let mut t1 = MerkleTree::new();
+        let mut t2 = MerkleTree::new();
+
+        for i in 0..1000 {
+            t1.toggle(item_hash(&i));
+            t2.toggle(item_hash(&i));
+        }
+
+        assert!(t1.diff(&t2).is_empty());
+
+        t2.toggle(item_hash(&1000));
+
+        let buckets = t1.diff(&t2);
+
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets[0].is_leaf());
+        assert!(buckets[0].contains(item_hash(&1000)));
+        assert!(MerklePath::root().contains(item_hash(&1000)));
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashSet;
+
+use core::StateRDT;
+
+// Ranges with at most this many items are sent in full instead of being
+// split further.
+const ITEM_THRESHOLD: usize = 8;
+const SPLIT_FANOUT: usize = 16;
+
+// States that can be cut into independently transferable items, each
+// identified by a hash. Reconciliation orders items by that hash.
+pub trait Reconcilable: StateRDT + Sized {
+    fn item_hashes(&self) -> Vec<u64>;
+
+    fn extract<F: Fn(u64) -> bool>(&self, keep: F) -> Self;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub struct HashRange {
+    lower: u64,
+    upper: Option<u64>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum ReconcileMessage<T> {
+    Fingerprint(HashRange, u64),
+    Items(HashRange, T, bool),
+    Done,
+}
+
+// The sorted item hashes are kept between rounds and only rebuilt after
+// items have been merged into the target.
+pub struct Reconciler<'a, T: Reconcilable + 'a> {
+    target: &'a mut T,
+    hashes: Vec<u64>,
+    stale: bool,
+    rounds: usize,
+    done: bool,
+}
+
+impl HashRange {
+    pub fn full() -> HashRange {
+        HashRange { lower: 0, upper: None }
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        hash >= self.lower && self.upper.map_or(true, |upper| hash < upper)
+    }
+}
+
+impl<'a, T: Reconcilable> Reconciler<'a, T> {
+    pub fn new(target: &'a mut T) -> Reconciler<'a, T> {
+        let hashes = sorted_hashes(target);
+
+        Reconciler {
+            target: target,
+            hashes: hashes,
+            stale: false,
+            rounds: 0,
+            done: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    pub fn start(&mut self) -> Vec<ReconcileMessage<T>> {
+        self.refresh_hashes();
+
+        let range = HashRange::full();
+
+        vec![ReconcileMessage::Fingerprint(range, fingerprint(&self.hashes, &range))]
+    }
+
+    // Handles every message of one round and returns the next round for the
+    // peer. When nothing is left to send, the reply is a single `Done`
+    // message; receiving `Done` ends the exchange with an empty reply.
+    pub fn receive(&mut self, messages: &[ReconcileMessage<T>]) -> Vec<ReconcileMessage<T>> {
+        let mut reply = Vec::new();
+        let mut peer_done = false;
+
+        for message in messages {
+            match message {
+                &ReconcileMessage::Fingerprint(ref range, theirs) => {
+                    self.refresh_hashes();
+
+                    if fingerprint(&self.hashes, range) != theirs {
+                        reply.extend(self.split(&self.hashes, range));
+                    }
+                },
+                &ReconcileMessage::Items(ref range, ref state, wants_reply) => {
+                    self.target.merge(state);
+                    self.stale = true;
+
+                    if wants_reply {
+                        let received = state.item_hashes().into_iter().collect::<HashSet<_>>();
+                        let missing = self.target.extract(|h| range.contains(h) && !received.contains(&h));
+
+                        if !missing.item_hashes().is_empty() {
+                            reply.push(ReconcileMessage::Items(range.clone(), missing, false));
+                        }
+                    }
+                },
+                &ReconcileMessage::Done => {
+                    peer_done = true;
+                },
+            }
+        }
+
+        self.rounds += 1;
+
+        if reply.is_empty() {
+            self.done = true;
+
+            if !peer_done {
+                reply.push(ReconcileMessage::Done);
+            }
+        }
+
+        reply
+    }
+
+    fn refresh_hashes(&mut self) {
+        if self.stale {
+            self.hashes = sorted_hashes(self.target);
+            self.stale = false;
+        }
+    }
+
+    // Small ranges are sent in full. Larger ones are cut into sub-ranges
+    // holding roughly the same number of our items.
+    fn split(&self, hashes: &[u64], range: &HashRange) -> Vec<ReconcileMessage<T>> {
+        let items = in_range(hashes, range);
+
+        if items.len() <= ITEM_THRESHOLD {
+            let range = range.clone();
+
+            return vec![ReconcileMessage::Items(range, self.target.extract(|h| range.contains(h)), true)];
+        }
+
+        let chunk = (items.len() + SPLIT_FANOUT - 1) / SPLIT_FANOUT;
+        let bounds = items.chunks(chunk).skip(1).map(|c| c[0]).collect::<Vec<_>>();
+
+        let lowers = Some(range.lower).into_iter().chain(bounds.iter().cloned());
+        let uppers = bounds.iter().cloned().map(Some).chain(Some(range.upper));
+
+        lowers.zip(uppers)
+            .map(|(lower, upper)| {
+                let sub = HashRange { lower: lower, upper: upper };
+
+                ReconcileMessage::Fingerprint(sub, fingerprint(items, &sub))
+            })
+            .collect()
+    }
+}
+
+fn sorted_hashes<T: Reconcilable>(target: &T) -> Vec<u64> {
+    let mut hashes = target.item_hashes();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+// Hashes are kept sorted, so the items in a range form a contiguous slice.
+fn in_range<'h>(hashes: &'h [u64], range: &HashRange) -> &'h [u64] {
+    let position = |bound: u64| match hashes.binary_search(&bound) {
+        Ok(i) | Err(i) => i,
+    };
+
+    let start = position(range.lower);
+    let end = range.upper.map_or(hashes.len(), position);
+
+    &hashes[start..end]
+}
+
+fn fingerprint(hashes: &[u64], range: &HashRange) -> u64 {
+    in_range(hashes, range).iter().fold(0, |acc, &h| acc ^ h)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Reconcilable, Reconciler};
+
+    use core::StateRDT;
+    use sets::{GSet, TwoPhaseSet, ObserveRemoveSet};
+
+    // Runs the protocol between two replicas in-process and returns the
+    // number of messages exchanged.
+    fn reconcile<T: Reconcilable>(s1: &mut T, s2: &mut T) -> usize {
+        let mut r1 = Reconciler::new(s1);
+        let mut r2 = Reconciler::new(s2);
+
+        let mut messages = r1.start();
+
+        while !messages.is_empty() {
+            messages = r2.receive(&messages);
+
+            if messages.is_empty() {
+                break;
+            }
+
+            messages = r1.receive(&messages);
+        }
+
+        assert!(r1.is_done() && r2.is_done());
+
+        r1.rounds() + r2.rounds() + 1
+    }
+
+    #[test]
+    fn reconcile_equal_g_sets() {
+        let mut s1 = GSet::new();
+        let mut s2 = GSet::new();
+
+        for i in 0..100 {
+            s1.add(i);
+            s2.add(i);
+        }
+
+        assert_eq!(reconcile(&mut s1, &mut s2), 3);
+    }
+
+    #[test]
+    fn reconcile_g_sets() {
+        let mut s1 = GSet::new();
+        let mut s2 = GSet::new();
+
+        for i in 0..10000 {
+            s1.add(i);
+            s2.add(i);
+        }
+
+        s1.add(-1).unwrap();
+        s2.add(-2).unwrap();
+        s2.add(-3).unwrap();
+
+        let messages = reconcile(&mut s1, &mut s2);
+
+        assert!(messages <= 9);
+        assert_eq!(s1.value(), s2.value());
+        assert_eq!(s1.value().len(), 10003);
+    }
+
+    #[test]
+    fn reconcile_with_empty_g_set() {
+        let mut s1 = GSet::new();
+        let mut s2 = GSet::new();
+
+        for i in 0..1000 {
+            s1.add(i);
+        }
+
+        reconcile(&mut s2, &mut s1);
+
+        assert_eq!(s1.value(), s2.value());
+    }
+
+    #[test]
+    fn reconcile_2p_sets() {
+        let mut s1 = TwoPhaseSet::new();
+        let mut s2 = TwoPhaseSet::new();
+
+        for i in 0..500 {
+            s1.add(i);
+        }
+
+        s2.merge(&s1);
+        s1.remove(10).unwrap();
+        s2.add(1000).unwrap();
+
+        reconcile(&mut s1, &mut s2);
+
+        assert_eq!(s1.value(), s2.value());
+        assert!(!s2.value().contains(&10));
+        assert!(s1.value().contains(&1000));
+    }
+
+    #[test]
+    fn reconcile_or_sets() {
+        let mut s1 = ObserveRemoveSet::new();
+        let mut s2 = ObserveRemoveSet::new();
+
+        for i in 0..500 {
+            s1.add(i);
+        }
+
+        s2.merge(&s1);
+        s1.remove(10).unwrap();
+        s2.remove(20).unwrap();
+        s2.add(20);
+
+        reconcile(&mut s1, &mut s2);
+
+        assert_eq!(s1.value(), s2.value());
+        assert!(!s1.value().contains(&10));
+        assert!(s1.value().contains(&20));
+        assert_eq!(s1.item_hashes().len(), s2.item_hashes().len());
+    }
+}
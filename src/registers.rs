@@ -1,21 +1,39 @@
 use std::default::Default;
+use std::hash::Hash;
 
 use chrono::{NaiveDateTime, DateTime, UTC, TimeZone};
 
-use core::{StateRDT, OperationRDT};
+use core::{StateRDT, OperationRDT, ValueFactory, DiffRDT};
+use merkle::{Digest, item_hash};
 
-#[derive(RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct LWWRegister<T: Default + Clone> {
     value: T,
     timestamp: DateTime<UTC>,
 }
 
-#[derive(RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct SetLWWRegisterOperation<T: Default + Clone> {
     value: T,
     timestamp: DateTime<UTC>,
 }
 
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MaxRegister<T: Ord + Clone> {
+    value: Option<T>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct SetMaxRegisterOperation<T>(T);
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MinRegister<T: Ord + Clone> {
+    value: Option<T>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct SetMinRegisterOperation<T>(T);
+
 impl<T: Default + Clone> LWWRegister<T> {
     pub fn new() -> LWWRegister<T> {
         LWWRegister {
@@ -60,10 +78,242 @@ impl<T: Default + Clone> StateRDT for LWWRegister<T> {
     }
 }
 
+impl<T: Ord + Clone> MaxRegister<T> {
+    pub fn new() -> MaxRegister<T> {
+        MaxRegister {
+            value: None,
+        }
+    }
+
+    pub fn value<'a>(&'a self) -> Option<&'a T> {
+        self.value.as_ref()
+    }
+
+    pub fn set(&mut self, value: T) -> Option<SetMaxRegisterOperation<T>> {
+        if let Some(ref cur_value) = self.value {
+            if value <= *cur_value {
+                return None;
+            }
+        }
+
+        let op = SetMaxRegisterOperation(value);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    fn join(&mut self, value: &T) {
+        let replace = match self.value {
+            Some(ref cur_value) => value > cur_value,
+            None => true,
+        };
+
+        if replace {
+            self.value = Some(value.clone());
+        }
+    }
+}
+
+impl<T: Ord + Clone> OperationRDT for MaxRegister<T> {
+    type Operation = SetMaxRegisterOperation<T>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        let &SetMaxRegisterOperation(ref value) = op;
+
+        self.join(value);
+    }
+}
+
+impl<T: Ord + Clone> StateRDT for MaxRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if let Some(ref value) = other.value {
+            self.join(value);
+        }
+    }
+}
+
+impl<T: Ord + Clone> MinRegister<T> {
+    pub fn new() -> MinRegister<T> {
+        MinRegister {
+            value: None,
+        }
+    }
+
+    pub fn value<'a>(&'a self) -> Option<&'a T> {
+        self.value.as_ref()
+    }
+
+    pub fn set(&mut self, value: T) -> Option<SetMinRegisterOperation<T>> {
+        if let Some(ref cur_value) = self.value {
+            if value >= *cur_value {
+                return None;
+            }
+        }
+
+        let op = SetMinRegisterOperation(value);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    fn join(&mut self, value: &T) {
+        let replace = match self.value {
+            Some(ref cur_value) => value < cur_value,
+            None => true,
+        };
+
+        if replace {
+            self.value = Some(value.clone());
+        }
+    }
+}
+
+impl<T: Ord + Clone> OperationRDT for MinRegister<T> {
+    type Operation = SetMinRegisterOperation<T>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        let &SetMinRegisterOperation(ref value) = op;
+
+        self.join(value);
+    }
+}
+
+impl<T: Ord + Clone> StateRDT for MinRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        if let Some(ref value) = other.value {
+            self.join(value);
+        }
+    }
+}
+
+impl<T: Default + Clone> DiffRDT for LWWRegister<T> {
+    type Summary = DateTime<UTC>;
+    type Delta = Option<SetLWWRegisterOperation<T>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.timestamp.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if self.timestamp > *summary {
+            Some(SetLWWRegisterOperation { value: self.value.clone(), timestamp: self.timestamp.clone() })
+        }
+        else {
+            None
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref op) = *delta {
+            self.apply(op);
+        }
+    }
+}
+
+impl<T: Ord + Clone> DiffRDT for MaxRegister<T> {
+    type Summary = Option<T>;
+    type Delta = Option<SetMaxRegisterOperation<T>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.value.clone()
+    }
+
+    // None orders below every value, which is exactly what a peer without
+    // a value needs.
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if self.value > *summary {
+            self.value.clone().map(SetMaxRegisterOperation)
+        }
+        else {
+            None
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref op) = *delta {
+            self.apply(op);
+        }
+    }
+}
+
+impl<T: Ord + Clone> DiffRDT for MinRegister<T> {
+    type Summary = Option<T>;
+    type Delta = Option<SetMinRegisterOperation<T>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.value.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let ahead = match (&self.value, summary) {
+            (&Some(ref value), &Some(ref theirs)) => value < theirs,
+            (&Some(_), &None) => true,
+            (&None, _) => false,
+        };
+
+        if ahead {
+            self.value.clone().map(SetMinRegisterOperation)
+        }
+        else {
+            None
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref op) = *delta {
+            self.apply(op);
+        }
+    }
+}
+
+impl<K, T: Default + Clone> ValueFactory<K> for LWWRegister<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        LWWRegister::new()
+    }
+}
+
+impl<K, T: Ord + Clone> ValueFactory<K> for MaxRegister<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        MaxRegister::new()
+    }
+}
+
+impl<K, T: Ord + Clone> ValueFactory<K> for MinRegister<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        MinRegister::new()
+    }
+}
+
+impl<T: Default + Clone + Hash> Digest for LWWRegister<T> {
+    fn digest(&self) -> u64 {
+        item_hash(&(&self.value, &self.timestamp))
+    }
+}
+
+impl<T: Ord + Clone + Hash> Digest for MaxRegister<T> {
+    fn digest(&self) -> u64 {
+        item_hash(&self.value)
+    }
+}
+
+impl<T: Ord + Clone + Hash> Digest for MinRegister<T> {
+    fn digest(&self) -> u64 {
+        item_hash(&self.value)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::LWWRegister;
-    use core::{StateRDT, OperationRDT};
+    use super::{LWWRegister, MaxRegister, MinRegister};
+    use core::{StateRDT, OperationRDT, DiffRDT};
 
     #[test]
     fn make_lww_register() {
@@ -110,4 +360,114 @@ mod test {
         assert_eq!(r1.value(), &"last");
         assert_eq!(r2.value(), &"last");
     }
+
+    #[test]
+    fn diff_lww_register_state() {
+        let mut r1 = LWWRegister::new();
+        let mut r2 = LWWRegister::new();
+
+        r1.set("first");
+        r2.set("last");
+
+        assert!(r1.diff(&r2.summary()).is_none());
+
+        let delta = r2.diff(&r1.summary());
+        r1.apply_diff(&delta);
+
+        assert_eq!(r1.value(), &"last");
+    }
+
+    #[test]
+    fn make_max_register() {
+        let register: MaxRegister<i32> = MaxRegister::new();
+
+        assert_eq!(register.value(), None);
+    }
+
+    #[test]
+    fn set_max_register() {
+        let mut register = MaxRegister::new();
+
+        register.set(5).unwrap();
+        assert!(register.set(3).is_none());
+
+        assert_eq!(register.value(), Some(&5));
+    }
+
+    #[test]
+    fn apply_max_register_set() {
+        let mut r1 = MaxRegister::new();
+        let mut r2 = MaxRegister::new();
+
+        let op1 = r1.set(5).unwrap();
+        let op2 = r2.set(7).unwrap();
+
+        r1.apply(&op2);
+        r2.apply(&op1);
+
+        assert_eq!(r1.value(), Some(&7));
+        assert_eq!(r2.value(), Some(&7));
+    }
+
+    #[test]
+    fn merge_max_register_state() {
+        let mut r1 = MaxRegister::new();
+        let mut r2 = MaxRegister::new();
+
+        r1.set(5);
+        r2.set(7);
+
+        r1.merge(&r2);
+        r2.merge(&r1);
+
+        assert_eq!(r1.value(), Some(&7));
+        assert_eq!(r2.value(), Some(&7));
+    }
+
+    #[test]
+    fn make_min_register() {
+        let register: MinRegister<i32> = MinRegister::new();
+
+        assert_eq!(register.value(), None);
+    }
+
+    #[test]
+    fn set_min_register() {
+        let mut register = MinRegister::new();
+
+        register.set(5).unwrap();
+        assert!(register.set(7).is_none());
+
+        assert_eq!(register.value(), Some(&5));
+    }
+
+    #[test]
+    fn apply_min_register_set() {
+        let mut r1 = MinRegister::new();
+        let mut r2 = MinRegister::new();
+
+        let op1 = r1.set(5).unwrap();
+        let op2 = r2.set(7).unwrap();
+
+        r1.apply(&op2);
+        r2.apply(&op1);
+
+        assert_eq!(r1.value(), Some(&5));
+        assert_eq!(r2.value(), Some(&5));
+    }
+
+    #[test]
+    fn merge_min_register_state() {
+        let mut r1 = MinRegister::new();
+        let mut r2 = MinRegister::new();
+
+        r1.set(5);
+        r2.set(7);
+
+        r1.merge(&r2);
+        r2.merge(&r1);
+
+        assert_eq!(r1.value(), Some(&5));
+        assert_eq!(r2.value(), Some(&5));
+    }
 }
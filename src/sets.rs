@@ -1,22 +1,29 @@
-use std::collections::{HashSet, HashMap};
+use std::collections::{HashSet, HashMap, BTreeMap, BTreeSet};
+use std::collections::Bound::Included;
 use std::hash::Hash;
 
+use rustc_serialize::{Encodable, Decodable, Encoder, Decoder};
 use uuid::Uuid;
 
-use core::{StateRDT, OperationRDT};
+use core::{StateRDT, OperationRDT, ValueFactory, BatchRDT, DiffRDT};
+use lattices::{SetUnion, Pair};
+use merkle::{MerkleTree, MerklePath, Digest, item_hash, in_buckets};
+use reconcile::Reconcilable;
+use registers::{LWWRegister, SetLWWRegisterOperation};
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone)]
 pub struct GSet<T: Hash + Eq + Clone> {
-    set: HashSet<T>
+    set: SetUnion<T>,
+    merkle: MerkleTree,
 }
 
 #[derive(Debug, RustcEncodable, RustcDecodable)]
 pub struct AddGSetOperation<T>(T);
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone)]
 pub struct TwoPhaseSet<T: Hash + Eq + Clone> {
-    members: HashSet<T>,
-    tombstones: HashSet<T>,
+    sets: Pair<SetUnion<T>, SetUnion<T>>,
+    merkle: MerkleTree,
 }
 
 #[derive(Debug, RustcEncodable, RustcDecodable)]
@@ -25,27 +32,71 @@ pub enum TwoPhaseSetOperation<T: Hash + Eq + Clone> {
     Remove(T),
 }
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone)]
 pub struct ObserveRemoveSet<T: Hash + Eq + Clone> {
     members: HashMap<T, HashSet<Uuid>>,
     tombstones: HashSet<Uuid>,
+    merkle: MerkleTree,
 }
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub enum ORSetOperation<T> {
     Add(T, Uuid),
     Remove(HashSet<Uuid>),
 }
 
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub struct ORSetBatch<T> {
+    adds: Vec<(T, Uuid)>,
+    removes: HashSet<Uuid>,
+}
+
+// Sets are encoded without their Merkle tree, which is rebuilt from the
+// items on decode. These hold the remaining fields, so the encoding is the
+// same as before the tree was added.
+#[derive(RustcEncodable, RustcDecodable)]
+struct GSetState<S> {
+    set: S,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct TwoPhaseSetState<S> {
+    sets: S,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct ObserveRemoveSetState<M, R> {
+    members: M,
+    tombstones: R,
+}
+
+// Live members are indexed by score so score ranges and iteration come
+// out in order without sorting. The index keeps no subtree counts, so rank
+// queries still walk every distinct score below the one asked about.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct SortedSet<T: Hash + Eq + Ord + Clone, S: Ord + Default + Clone> {
+    members: ObserveRemoveSet<T>,
+    scores: HashMap<T, LWWRegister<S>>,
+    index: BTreeMap<S, BTreeSet<T>>,
+    size: usize,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct SortedSetOperation<T, S: Default + Clone> {
+    member_op: Option<ORSetOperation<T>>,
+    score_op: Option<(T, SetLWWRegisterOperation<S>)>,
+}
+
 impl<T: Hash + Eq + Clone> GSet<T> {
     pub fn new() -> GSet<T> {
         GSet {
-            set: HashSet::new(),
+            set: SetUnion::new(),
+            merkle: MerkleTree::new(),
         }
     }
 
     pub fn value<'a>(&'a self) -> &'a HashSet<T> {
-        &self.set
+        self.set.value()
     }
 
     pub fn add(&mut self, value: T) -> Option<AddGSetOperation<T>> {
@@ -59,6 +110,24 @@ impl<T: Hash + Eq + Clone> GSet<T> {
 
         Some(op)
     }
+
+    pub fn merkle<'a>(&'a self) -> &'a MerkleTree {
+        &self.merkle
+    }
+
+    // Returns the part of the state that falls into the given Merkle
+    // buckets, ready to be merged into a replica that lacks it.
+    pub fn delta(&self, buckets: &[MerklePath]) -> GSet<T> {
+        self.extract(|hash| in_buckets(buckets, hash))
+    }
+
+    fn insert(&mut self, value: T) {
+        let hash = item_hash(&value);
+
+        if self.set.insert(value) {
+            self.merkle.toggle(hash);
+        }
+    }
 }
 
 impl<T: Hash + Eq + Clone> OperationRDT for GSet<T> {
@@ -67,26 +136,91 @@ impl<T: Hash + Eq + Clone> OperationRDT for GSet<T> {
     fn apply(&mut self, op: &Self::Operation) {
         let &AddGSetOperation(ref value) = op;
 
-        self.set.insert(value.clone());
+        self.insert(value.clone());
     }
 }
 
 impl<T: Hash + Eq + Clone> StateRDT for GSet<T> {
     fn merge(&mut self, other: &GSet<T>) {
-        self.set = self.set.union(&other.set).cloned().collect();
+        for value in other.set.value() {
+            self.insert(value.clone());
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> Digest for GSet<T> {
+    fn digest(&self) -> u64 {
+        self.merkle.root()
+    }
+}
+
+impl<T: Hash + Eq + Clone + Encodable> Encodable for GSet<T> {
+    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        GSetState { set: &self.set }.encode(e)
+    }
+}
+
+impl<T: Hash + Eq + Clone + Decodable> Decodable for GSet<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<GSet<T>, D::Error> {
+        let state: GSetState<SetUnion<T>> = try!(Decodable::decode(d));
+        let mut set = GSet { set: state.set, merkle: MerkleTree::new() };
+
+        set.merkle = set.item_hashes().into_iter().collect();
+
+        Ok(set)
+    }
+}
+
+impl<T: Hash + Eq + Clone> Reconcilable for GSet<T> {
+    fn item_hashes(&self) -> Vec<u64> {
+        self.set.value().iter().map(item_hash).collect()
+    }
+
+    fn extract<F: Fn(u64) -> bool>(&self, keep: F) -> GSet<T> {
+        let mut part = GSet::new();
+
+        for value in self.set.value() {
+            if keep(item_hash(value)) {
+                part.insert(value.clone());
+            }
+        }
+
+        part
+    }
+}
+
+// Summaries are Merkle trees; the delta holds every item in the buckets
+// where the trees disagree.
+impl<T: Hash + Eq + Clone> DiffRDT for GSet<T> {
+    type Summary = MerkleTree;
+    type Delta = GSet<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.merkle.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.delta(&self.merkle.diff(summary))
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
     }
 }
 
 impl<T: Hash + Eq + Clone> TwoPhaseSet<T> {
     pub fn new() -> TwoPhaseSet<T> {
         TwoPhaseSet {
-            members: HashSet::new(),
-            tombstones: HashSet::new(),
+            sets: Pair::new(SetUnion::new(), SetUnion::new()),
+            merkle: MerkleTree::new(),
         }
     }
 
     pub fn value(&self) -> HashSet<T> {
-        self.members.difference(&self.tombstones).cloned().collect()
+        let members = self.sets.first().value();
+        let tombstones = self.sets.second().value();
+
+        members.difference(tombstones).cloned().collect()
     }
 
     pub fn add(&mut self, value: T) -> Option<TwoPhaseSetOperation<T>> {
@@ -112,6 +246,31 @@ impl<T: Hash + Eq + Clone> TwoPhaseSet<T> {
 
         Some(op)
     }
+
+    pub fn merkle<'a>(&'a self) -> &'a MerkleTree {
+        &self.merkle
+    }
+
+    pub fn delta(&self, buckets: &[MerklePath]) -> TwoPhaseSet<T> {
+        self.extract(|hash| in_buckets(buckets, hash))
+    }
+
+    // Additions and removals of the same value are distinct items in the
+    // Merkle tree.
+    fn insert(&mut self, removed: bool, value: T) {
+        let hash = item_hash(&(removed, &value));
+
+        let inserted = if removed {
+            self.sets.second_mut().insert(value)
+        }
+        else {
+            self.sets.first_mut().insert(value)
+        };
+
+        if inserted {
+            self.merkle.toggle(hash);
+        }
+    }
 }
 
 impl<T: Hash + Eq + Clone> OperationRDT for TwoPhaseSet<T> {
@@ -121,16 +280,88 @@ impl<T: Hash + Eq + Clone> OperationRDT for TwoPhaseSet<T> {
         use self::TwoPhaseSetOperation::{Add, Remove};
 
         match op {
-            &Add(ref value) => self.members.insert(value.clone()),
-            &Remove(ref value) => self.tombstones.insert(value.clone()),
+            &Add(ref value) => self.insert(false, value.clone()),
+            &Remove(ref value) => self.insert(true, value.clone()),
         };
     }
 }
 
 impl<T: Hash + Eq + Clone> StateRDT for TwoPhaseSet<T> {
     fn merge(&mut self, other: &TwoPhaseSet<T>) {
-        self.members = self.members.union(&other.members).cloned().collect();
-        self.tombstones = self.tombstones.union(&other.tombstones).cloned().collect();
+        for value in other.sets.first().value() {
+            self.insert(false, value.clone());
+        }
+
+        for value in other.sets.second().value() {
+            self.insert(true, value.clone());
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> Digest for TwoPhaseSet<T> {
+    fn digest(&self) -> u64 {
+        self.merkle.root()
+    }
+}
+
+impl<T: Hash + Eq + Clone> Reconcilable for TwoPhaseSet<T> {
+    fn item_hashes(&self) -> Vec<u64> {
+        let added = self.sets.first().value().iter().map(|value| item_hash(&(false, value)));
+        let removed = self.sets.second().value().iter().map(|value| item_hash(&(true, value)));
+
+        added.chain(removed).collect()
+    }
+
+    fn extract<F: Fn(u64) -> bool>(&self, keep: F) -> TwoPhaseSet<T> {
+        let mut part = TwoPhaseSet::new();
+
+        for value in self.sets.first().value() {
+            if keep(item_hash(&(false, value))) {
+                part.insert(false, value.clone());
+            }
+        }
+
+        for value in self.sets.second().value() {
+            if keep(item_hash(&(true, value))) {
+                part.insert(true, value.clone());
+            }
+        }
+
+        part
+    }
+}
+
+impl<T: Hash + Eq + Clone + Encodable> Encodable for TwoPhaseSet<T> {
+    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        TwoPhaseSetState { sets: &self.sets }.encode(e)
+    }
+}
+
+impl<T: Hash + Eq + Clone + Decodable> Decodable for TwoPhaseSet<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<TwoPhaseSet<T>, D::Error> {
+        let state: TwoPhaseSetState<Pair<SetUnion<T>, SetUnion<T>>> = try!(Decodable::decode(d));
+        let mut set = TwoPhaseSet { sets: state.sets, merkle: MerkleTree::new() };
+
+        set.merkle = set.item_hashes().into_iter().collect();
+
+        Ok(set)
+    }
+}
+
+impl<T: Hash + Eq + Clone> DiffRDT for TwoPhaseSet<T> {
+    type Summary = MerkleTree;
+    type Delta = TwoPhaseSet<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.merkle.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.delta(&self.merkle.diff(summary))
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
     }
 }
 
@@ -139,6 +370,7 @@ impl<T: Hash + Eq + Clone> ObserveRemoveSet<T> {
         ObserveRemoveSet {
             members: HashMap::new(),
             tombstones: HashSet::new(),
+            merkle: MerkleTree::new(),
         }
     }
 
@@ -151,6 +383,19 @@ impl<T: Hash + Eq + Clone> ObserveRemoveSet<T> {
             .collect()
     }
 
+    pub fn contains(&self, value: &T) -> bool {
+        self.members
+            .get(value)
+            .map_or(false, |ids| !ids.is_subset(&self.tombstones))
+    }
+
+    // The tags of every add of `value` that has not been removed.
+    pub fn tags(&self, value: &T) -> HashSet<Uuid> {
+        self.members
+            .get(value)
+            .map_or(HashSet::new(), |ids| ids.difference(&self.tombstones).cloned().collect())
+    }
+
     pub fn add(&mut self, value: T) -> ORSetOperation<T> {
         let op = ORSetOperation::Add(value, Uuid::new_v4());
 
@@ -171,6 +416,51 @@ impl<T: Hash + Eq + Clone> ObserveRemoveSet<T> {
 
         Some(op)
     }
+
+    pub fn merkle<'a>(&'a self) -> &'a MerkleTree {
+        &self.merkle
+    }
+
+    pub fn delta(&self, buckets: &[MerklePath]) -> ObserveRemoveSet<T> {
+        self.extract(|hash| in_buckets(buckets, hash))
+    }
+
+    // Members are encoded in hash order and tags sorted, so replicas holding
+    // the same set encode it to the same bytes.
+    fn sorted_members(&self) -> Vec<(&T, Vec<&Uuid>)> {
+        let mut members = self.members.iter()
+            .map(|(value, ids)| {
+                let mut ids = ids.iter().collect::<Vec<_>>();
+                ids.sort();
+                (value, ids)
+            })
+            .collect::<Vec<_>>();
+
+        members.sort_by_key(|&(value, _)| item_hash(value));
+        members
+    }
+
+    fn sorted_tombstones(&self) -> Vec<&Uuid> {
+        let mut tombstones = self.tombstones.iter().collect::<Vec<_>>();
+        tombstones.sort();
+        tombstones
+    }
+
+    fn insert_tag(&mut self, value: T, id: Uuid) {
+        let hash = item_hash(&(&value, &id));
+
+        if self.members.entry(value).or_insert(HashSet::new()).insert(id) {
+            self.merkle.toggle(hash);
+        }
+    }
+
+    fn insert_tombstone(&mut self, id: Uuid) {
+        let hash = item_hash(&id);
+
+        if self.tombstones.insert(id) {
+            self.merkle.toggle(hash);
+        }
+    }
 }
 
 impl<T: Hash + Eq + Clone> OperationRDT for ObserveRemoveSet<T> {
@@ -181,44 +471,454 @@ impl<T: Hash + Eq + Clone> OperationRDT for ObserveRemoveSet<T> {
 
         match op {
             &Add(ref value, ref id) => {
-                let ids = self.members.entry(value.clone()).or_insert(HashSet::new());
-                ids.insert(id.clone());
+                self.insert_tag(value.clone(), id.clone());
             },
             &Remove(ref uuids) => {
-                self.tombstones = self.tombstones.union(uuids).cloned().collect();
+                for id in uuids {
+                    self.insert_tombstone(id.clone());
+                }
             },
         }
     }
 }
 
+impl<T> ORSetBatch<T> {
+    pub fn added(&self) -> Vec<&T> {
+        self.adds.iter().map(|&(ref value, _)| value).collect()
+    }
+}
+
+impl<T: Hash + Eq + Clone> BatchRDT for ObserveRemoveSet<T> {
+    type Batch = ORSetBatch<T>;
+
+    // Tags are unique, so an add and a remove of the same tag within one
+    // batch cancel out and neither has to be sent.
+    fn batch(ops: Vec<Self::Operation>) -> Option<Self::Batch> {
+        use self::ORSetOperation::{Add, Remove};
+
+        if ops.is_empty() {
+            return None;
+        }
+
+        let mut adds = Vec::new();
+        let mut removes = HashSet::new();
+
+        for op in ops {
+            match op {
+                Add(value, id) => adds.push((value, id)),
+                Remove(ids) => removes.extend(ids),
+            }
+        }
+
+        let cancelled = adds.iter()
+            .filter(|&&(_, ref id)| removes.contains(id))
+            .map(|&(_, ref id)| id.clone())
+            .collect::<HashSet<_>>();
+
+        adds.retain(|&(_, ref id)| !cancelled.contains(id));
+
+        Some(ORSetBatch {
+            adds: adds,
+            removes: removes.difference(&cancelled).cloned().collect(),
+        })
+    }
+
+    fn apply_batch(&mut self, batch: &Self::Batch) {
+        for &(ref value, ref id) in &batch.adds {
+            self.insert_tag(value.clone(), id.clone());
+        }
+
+        for id in &batch.removes {
+            self.insert_tombstone(id.clone());
+        }
+    }
+}
+
 impl<T: Hash + Eq + Clone> StateRDT for ObserveRemoveSet<T> {
     fn merge(&mut self, other: &Self) {
-        use std::collections::hash_map::Entry;
-
         for (value, ids) in &other.members {
-            match self.members.entry(value.clone()) {
-                Entry::Vacant(e) => {
-                    e.insert(ids.clone());
-                },
-                Entry::Occupied(mut e) => {
-                    let u = e.get().union(&ids).cloned().collect();
-                    e.insert(u);
-                },
+            for id in ids {
+                self.insert_tag(value.clone(), id.clone());
             }
         }
 
-        self.tombstones = self.tombstones.union(&other.tombstones).cloned().collect();
+        for id in &other.tombstones {
+            self.insert_tombstone(id.clone());
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> Digest for ObserveRemoveSet<T> {
+    fn digest(&self) -> u64 {
+        self.merkle.root()
+    }
+}
+
+impl<T: Hash + Eq + Clone> Reconcilable for ObserveRemoveSet<T> {
+    fn item_hashes(&self) -> Vec<u64> {
+        let tags = self.members.iter()
+            .flat_map(|(value, ids)| ids.iter().map(move |id| item_hash(&(value, id))));
+        let tombstones = self.tombstones.iter().map(item_hash);
+
+        tags.chain(tombstones).collect()
+    }
+
+    fn extract<F: Fn(u64) -> bool>(&self, keep: F) -> ObserveRemoveSet<T> {
+        let mut part = ObserveRemoveSet::new();
+
+        for (value, ids) in &self.members {
+            for id in ids {
+                if keep(item_hash(&(value, id))) {
+                    part.insert_tag(value.clone(), id.clone());
+                }
+            }
+        }
+
+        for id in &self.tombstones {
+            if keep(item_hash(id)) {
+                part.insert_tombstone(id.clone());
+            }
+        }
+
+        part
+    }
+}
+
+impl<T: Hash + Eq + Clone + Encodable> Encodable for ObserveRemoveSet<T> {
+    fn encode<E: Encoder>(&self, e: &mut E) -> Result<(), E::Error> {
+        ObserveRemoveSetState { members: self.sorted_members(), tombstones: self.sorted_tombstones() }.encode(e)
+    }
+}
+
+impl<T: Hash + Eq + Clone + Decodable> Decodable for ObserveRemoveSet<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<ObserveRemoveSet<T>, D::Error> {
+        let state: ObserveRemoveSetState<Vec<(T, Vec<Uuid>)>, Vec<Uuid>> = try!(Decodable::decode(d));
+        let mut set = ObserveRemoveSet {
+            members: state.members.into_iter().map(|(value, ids)| (value, ids.into_iter().collect())).collect(),
+            tombstones: state.tombstones.into_iter().collect(),
+            merkle: MerkleTree::new(),
+        };
+
+        set.merkle = set.item_hashes().into_iter().collect();
+
+        Ok(set)
+    }
+}
+
+impl<T: Hash + Eq + Clone> DiffRDT for ObserveRemoveSet<T> {
+    type Summary = MerkleTree;
+    type Delta = ObserveRemoveSet<T>;
+
+    fn summary(&self) -> Self::Summary {
+        self.merkle.clone()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        self.delta(&self.merkle.diff(summary))
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
+    }
+}
+
+impl<T, S> SortedSet<T, S>
+    where T: Hash + Eq + Ord + Clone,
+          S: Ord + Default + Clone
+{
+    pub fn new() -> SortedSet<T, S> {
+        SortedSet {
+            members: ObserveRemoveSet::new(),
+            scores: HashMap::new(),
+            index: BTreeMap::new(),
+            size: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains(&self, member: &T) -> bool {
+        self.members.contains(member)
+    }
+
+    pub fn score(&self, member: &T) -> Option<&S> {
+        if self.contains(member) {
+            self.scores.get(member).map(|r| r.value())
+        }
+        else {
+            None
+        }
+    }
+
+    // Linear in the number of distinct scores below the member's, plus the
+    // members tied with it.
+    pub fn rank(&self, member: &T) -> Option<usize> {
+        self.score(member).map(|score| {
+            let below = self.index.range(..score).map(|(_, members)| members.len()).sum::<usize>();
+
+            below + self.index[score].range(..member).count()
+        })
+    }
+
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(&'a T, &'a S)> + 'a> {
+        Box::new(self.index.iter().flat_map(|(score, members)| members.iter().map(move |member| (member, score))))
+    }
+
+    pub fn range_by_score(&self, min: &S, max: &S) -> Vec<(&T, &S)> {
+        if min > max {
+            return Vec::new();
+        }
+
+        self.index.range((Included(min), Included(max)))
+            .flat_map(|(score, members)| members.iter().map(move |member| (member, score)))
+            .collect()
+    }
+
+    // Skips whole scores until it reaches `start`, so the cost is linear in
+    // the distinct scores before it rather than the members.
+    pub fn range_by_rank(&self, start: usize, stop: usize) -> Vec<(&T, &S)> {
+        let mut skipped = 0;
+        let mut scores = self.index.iter().peekable();
+
+        while let Some(&(_, members)) = scores.peek() {
+            if skipped + members.len() > start {
+                break;
+            }
+
+            skipped += members.len();
+            scores.next();
+        }
+
+        scores.flat_map(|(score, members)| members.iter().map(move |member| (member, score)))
+            .skip(start - skipped)
+            .take(stop.saturating_sub(start))
+            .collect()
+    }
+
+    pub fn add(&mut self, member: T, score: S) -> SortedSetOperation<T, S> {
+        let op = SortedSetOperation {
+            member_op: Some(self.members.add(member.clone())),
+            score_op: Some(self.score_op(member, score)),
+        };
+
+        self.apply(&op);
+
+        op
+    }
+
+    pub fn set_score(&mut self, member: T, score: S) -> Option<SortedSetOperation<T, S>> {
+        if !self.contains(&member) {
+            return None;
+        }
+
+        let op = SortedSetOperation {
+            member_op: None,
+            score_op: Some(self.score_op(member, score)),
+        };
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn remove(&mut self, member: T) -> Option<SortedSetOperation<T, S>> {
+        if !self.contains(&member) {
+            return None;
+        }
+
+        let op = SortedSetOperation {
+            member_op: self.members.remove(member),
+            score_op: None,
+        };
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    fn score_op(&self, member: T, score: S) -> (T, SetLWWRegisterOperation<S>) {
+        let mut register = LWWRegister::new();
+
+        (member, register.set(score))
+    }
+
+    fn index_insert(&mut self, member: &T) {
+        if let Some(score) = self.score(member).cloned() {
+            if self.index.entry(score).or_insert(BTreeSet::new()).insert(member.clone()) {
+                self.size += 1;
+            }
+        }
+    }
+
+    fn index_remove(&mut self, member: &T) {
+        let score = match self.scores.get(member) {
+            Some(register) => register.value().clone(),
+            None => return,
+        };
+
+        let empty = match self.index.get_mut(&score) {
+            Some(members) => {
+                if members.remove(member) {
+                    self.size -= 1;
+                }
+
+                members.is_empty()
+            },
+            None => false,
+        };
+
+        if empty {
+            self.index.remove(&score);
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.index = BTreeMap::new();
+        self.size = 0;
+
+        let members = self.scores.keys().cloned().collect::<Vec<_>>();
+
+        for member in members {
+            self.index_insert(&member);
+        }
+    }
+
+    // Only members present in both the membership set and the score map are
+    // indexed, so the index is rebuilt per member around every change.
+    fn update_member<F: FnOnce(&mut Self)>(&mut self, member: &T, f: F) {
+        self.index_remove(member);
+
+        f(self);
+
+        self.index_insert(member);
+    }
+}
+
+impl<T, S> OperationRDT for SortedSet<T, S>
+    where T: Hash + Eq + Ord + Clone,
+          S: Ord + Default + Clone
+{
+    type Operation = SortedSetOperation<T, S>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        if let Some((ref member, ref score_op)) = op.score_op {
+            self.update_member(member, |s| {
+                s.scores.entry(member.clone())
+                    .or_insert(LWWRegister::new())
+                    .apply(score_op);
+            });
+        }
+
+        if let Some(ref member_op) = op.member_op {
+            let affected = match member_op {
+                &ORSetOperation::Add(ref member, _) => vec![member.clone()],
+                &ORSetOperation::Remove(_) => self.iter().map(|(m, _)| m.clone()).collect(),
+            };
+
+            self.members.apply(member_op);
+
+            for member in affected {
+                self.update_member(&member, |_| ());
+            }
+        }
+    }
+}
+
+impl<T, S> StateRDT for SortedSet<T, S>
+    where T: Hash + Eq + Ord + Clone,
+          S: Ord + Default + Clone
+{
+    fn merge(&mut self, other: &Self) {
+        self.members.merge(&other.members);
+
+        for (member, register) in &other.scores {
+            self.scores.entry(member.clone())
+                .or_insert(LWWRegister::new())
+                .merge(register);
+        }
+
+        self.reindex();
+    }
+}
+
+// Scores are folded into the membership tree as one (member, digest) item
+// per member.
+impl<T, S> DiffRDT for SortedSet<T, S>
+    where T: Hash + Eq + Ord + Clone,
+          S: Ord + Default + Clone + Hash
+{
+    type Summary = MerkleTree;
+    type Delta = SortedSet<T, S>;
+
+    fn summary(&self) -> Self::Summary {
+        let mut merkle = self.members.merkle().clone();
+
+        for (member, register) in &self.scores {
+            merkle.toggle(item_hash(&(member, register.digest())));
+        }
+
+        merkle
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().diff(summary);
+
+        let mut delta = SortedSet {
+            members: self.members.delta(&buckets),
+            scores: self.scores.iter()
+                .filter(|&(member, register)| in_buckets(&buckets, item_hash(&(member, register.digest()))))
+                .map(|(member, register)| (member.clone(), register.clone()))
+                .collect(),
+            index: BTreeMap::new(),
+            size: 0,
+        };
+
+        delta.reindex();
+
+        delta
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        self.merge(delta);
+    }
+}
+
+impl<K, T: Hash + Eq + Clone> ValueFactory<K> for GSet<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        GSet::new()
+    }
+}
+
+impl<K, T: Hash + Eq + Clone> ValueFactory<K> for TwoPhaseSet<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        TwoPhaseSet::new()
+    }
+}
+
+impl<K, T: Hash + Eq + Clone> ValueFactory<K> for ObserveRemoveSet<T> {
+    type Context = ();
+
+    fn create(_: &K, _: &()) -> Self {
+        ObserveRemoveSet::new()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{GSet, TwoPhaseSet, ObserveRemoveSet};
+    use super::{GSet, TwoPhaseSet, ObserveRemoveSet, SortedSet};
 
     use std::collections::HashSet;
     use std::iter::FromIterator;
 
-    use core::{StateRDT, OperationRDT};
+    use rustc_serialize::json;
+
+    use core::{StateRDT, OperationRDT, BatchRDT, BatchBuilder, DiffRDT};
 
     #[test]
     fn make_g_set() {
@@ -266,6 +966,31 @@ mod test {
         assert_eq!(*s2.value(), HashSet::from_iter(vec![123, 456]));
     }
 
+    #[test]
+    fn sync_g_set_merkle() {
+        let mut s1 = GSet::new();
+        let mut s2 = GSet::new();
+
+        for i in 0..1000 {
+            s1.add(i);
+            s2.add(i);
+        }
+
+        s1.add(1000).unwrap();
+        s2.add(1001).unwrap();
+
+        let buckets = s1.merkle().diff(s2.merkle());
+        let (d1, d2) = (s1.delta(&buckets), s2.delta(&buckets));
+
+        assert!(d1.value().len() < 10);
+
+        s1.merge(&d2);
+        s2.merge(&d1);
+
+        assert_eq!(s1.value(), s2.value());
+        assert_eq!(s1.merkle(), s2.merkle());
+    }
+
     #[test]
     fn make_2p_set() {
         let set: TwoPhaseSet<i32> = TwoPhaseSet::new();
@@ -414,4 +1139,193 @@ mod test {
         assert_eq!(s1.value(), HashSet::from_iter(vec![123, 456]));
         assert_eq!(s1.value(), HashSet::from_iter(vec![123, 456]));
     }
+
+    #[test]
+    fn sync_or_set_merkle() {
+        let mut s1 = ObserveRemoveSet::new();
+        let mut s2 = ObserveRemoveSet::new();
+
+        for i in 0..100 {
+            s1.add(i);
+        }
+
+        s2.merge(&s1);
+        s1.remove(5).unwrap();
+        s2.add(200);
+
+        let buckets = s1.merkle().diff(s2.merkle());
+
+        s1.merge(&s2.delta(&buckets));
+        s2.merge(&s1.delta(&buckets));
+
+        assert!(!s2.value().contains(&5));
+        assert_eq!(s1.value(), s2.value());
+        assert!(s1.merkle().diff(s2.merkle()).is_empty());
+    }
+
+    #[test]
+    fn diff_or_set_state() {
+        let mut s1 = ObserveRemoveSet::new();
+        let mut s2 = ObserveRemoveSet::new();
+
+        for i in 0..100 {
+            s1.add(i);
+        }
+
+        s2.merge(&s1);
+        s1.remove(10).unwrap();
+        s2.add(100);
+
+        let delta = s2.diff(&s1.summary());
+
+        assert!(delta.value().len() < 10);
+
+        s1.apply_diff(&delta);
+        s2.apply_diff(&s1.diff(&s2.summary()));
+
+        assert_eq!(s1.value(), s2.value());
+        assert!(!s2.value().contains(&10));
+        assert!(s1.value().contains(&100));
+    }
+
+    #[test]
+    fn apply_or_set_batch() {
+        let mut s1 = ObserveRemoveSet::new();
+        let mut s2 = ObserveRemoveSet::new();
+
+        let op = s1.add(1);
+        s2.apply(&op);
+
+        let batch = {
+            let mut builder = BatchBuilder::new(&mut s1);
+
+            builder.update(|s| Some(s.add(2)))
+                .update(|s| Some(s.add(3)))
+                .update(|s| s.remove(3))
+                .update(|s| s.remove(1));
+
+            builder.commit().unwrap()
+        };
+
+        s2.apply_batch(&batch);
+
+        assert_eq!(batch.added(), vec![&2]);
+        assert_eq!(s1.value(), HashSet::from_iter(vec![2]));
+        assert_eq!(s2.value(), HashSet::from_iter(vec![2]));
+    }
+
+    #[test]
+    fn encode_or_set_without_merkle() {
+        let mut s1 = ObserveRemoveSet::new();
+
+        s1.add("a".to_owned());
+        s1.add("b".to_owned());
+        s1.remove("a".to_owned()).unwrap();
+
+        let encoded = json::encode(&s1).unwrap();
+        let s2: ObserveRemoveSet<String> = json::decode(&encoded).unwrap();
+
+        assert!(!encoded.contains("merkle"));
+        assert_eq!(s2.value(), s1.value());
+        assert_eq!(s2.merkle(), s1.merkle());
+    }
+
+    #[test]
+    fn make_sorted_set() {
+        let set: SortedSet<&str, i32> = SortedSet::new();
+
+        assert_eq!(set.len(), 0);
+        assert!(set.range_by_rank(0, 10).is_empty());
+    }
+
+    #[test]
+    fn add_sorted_set() {
+        let mut set = SortedSet::new();
+
+        set.add("carol", 30);
+        set.add("alice", 10);
+        set.add("bob", 20);
+        set.set_score("alice", 25).unwrap();
+
+        assert!(set.set_score("dave", 5).is_none());
+        assert_eq!(set.rank(&"alice"), Some(1));
+        assert_eq!(set.range_by_rank(0, 2), vec![(&"bob", &20), (&"alice", &25)]);
+        assert_eq!(set.range_by_score(&21, &30), vec![(&"alice", &25), (&"carol", &30)]);
+    }
+
+    #[test]
+    fn remove_sorted_set() {
+        let mut set = SortedSet::new();
+
+        set.add("alice", 10);
+        set.add("bob", 20);
+        set.remove("alice").unwrap();
+
+        assert!(set.remove("alice").is_none());
+        assert_eq!(set.score(&"alice"), None);
+        assert_eq!(set.range_by_rank(0, 10), vec![(&"bob", &20)]);
+    }
+
+    #[test]
+    fn apply_sorted_set_ops() {
+        let mut s1 = SortedSet::new();
+        let mut s2 = SortedSet::new();
+
+        let op1 = s1.add("alice", 10);
+        let op2 = s2.add("bob", 5);
+        s1.apply(&op2);
+        s2.apply(&op1);
+
+        let op3 = s2.set_score("bob", 15).unwrap();
+        let op4 = s1.remove("alice").unwrap();
+        s1.apply(&op3);
+        s2.apply(&op4);
+
+        assert_eq!(s1.range_by_rank(0, 10), vec![(&"bob", &15)]);
+        assert_eq!(s2.range_by_rank(0, 10), vec![(&"bob", &15)]);
+    }
+
+    #[test]
+    fn merge_sorted_set_state() {
+        let mut s1 = SortedSet::new();
+        let mut s2 = SortedSet::new();
+
+        s1.add(1, "b");
+        s2.add(2, "a");
+        s2.add(3, "c");
+
+        s1.merge(&s2);
+        s2.merge(&s1);
+
+        assert_eq!(s1.range_by_score(&"a", &"b"), vec![(&2, &"a"), (&1, &"b")]);
+        assert_eq!(s2.range_by_rank(0, 3), s1.range_by_rank(0, 3));
+    }
+
+    #[test]
+    fn rank_sorted_set_ties() {
+        let mut set = SortedSet::new();
+
+        set.add("carol", 10);
+        set.add("alice", 10);
+        set.add("bob", 5);
+        set.add("dave", 20);
+
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.rank(&"bob"), Some(0));
+        assert_eq!(set.rank(&"alice"), Some(1));
+        assert_eq!(set.rank(&"carol"), Some(2));
+        assert_eq!(set.rank(&"dave"), Some(3));
+        assert_eq!(set.rank(&"erin"), None);
+        assert_eq!(set.range_by_rank(1, 3), vec![(&"alice", &10), (&"carol", &10)]);
+        assert_eq!(set.range_by_rank(3, 9), vec![(&"dave", &20)]);
+        assert_eq!(set.range_by_rank(2, 4), vec![(&"carol", &10), (&"dave", &20)]);
+        assert!(set.range_by_rank(4, 9).is_empty());
+        assert_eq!(set.range_by_score(&10, &10), vec![(&"alice", &10), (&"carol", &10)]);
+        assert!(set.range_by_score(&20, &10).is_empty());
+
+        set.remove("alice").unwrap();
+
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.rank(&"carol"), Some(1));
+    }
 }
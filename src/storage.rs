@@ -0,0 +1,601 @@
+use std::fmt;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::{Encodable, Decodable};
+use rustc_serialize::json;
+
+#[cfg(feature = "sqlite")]
+use rusqlite;
+
+use core::{StateRDT, OperationRDT};
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Encode(json::EncoderError),
+    Decode(json::DecoderError),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+pub trait Storage {
+    fn load_snapshot(&self) -> Result<Option<String>, StorageError>;
+
+    fn load_log(&self) -> Result<Vec<String>, StorageError>;
+
+    fn append_log(&mut self, entry: &str) -> Result<(), StorageError>;
+
+    // Replaces the snapshot and empties the log, which the new snapshot
+    // already covers.
+    fn save_snapshot(&mut self, snapshot: &str) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryStorage {
+    snapshot: Option<String>,
+    log: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+// Log entries and the snapshot carry sequence numbers, so entries that an
+// interrupted compaction left behind are not applied a second time.
+pub struct Persistent<T, S: Storage> {
+    value: T,
+    storage: S,
+    seq: u64,
+    log_len: usize,
+    compact_after: usize,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::Io(ref e) => write!(f, "storage I/O error: {}", e),
+            StorageError::Encode(ref e) => write!(f, "could not encode value: {}", e),
+            StorageError::Decode(ref e) => write!(f, "could not decode value: {}", e),
+            #[cfg(feature = "sqlite")]
+            StorageError::Sqlite(ref e) => write!(f, "SQLite error: {}", e),
+        }
+    }
+}
+
+impl Error for StorageError {
+    fn description(&self) -> &str {
+        match *self {
+            StorageError::Io(_) => "storage I/O error",
+            StorageError::Encode(_) => "could not encode value",
+            StorageError::Decode(_) => "could not decode value",
+            #[cfg(feature = "sqlite")]
+            StorageError::Sqlite(_) => "SQLite error",
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> StorageError {
+        StorageError::Io(e)
+    }
+}
+
+impl From<json::EncoderError> for StorageError {
+    fn from(e: json::EncoderError) -> StorageError {
+        StorageError::Encode(e)
+    }
+}
+
+impl From<json::DecoderError> for StorageError {
+    fn from(e: json::DecoderError) -> StorageError {
+        StorageError::Decode(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> StorageError {
+        StorageError::Sqlite(e)
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            snapshot: None,
+            log: Vec::new(),
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load_snapshot(&self) -> Result<Option<String>, StorageError> {
+        Ok(self.snapshot.clone())
+    }
+
+    fn load_log(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self.log.clone())
+    }
+
+    fn append_log(&mut self, entry: &str) -> Result<(), StorageError> {
+        self.log.push(entry.to_owned());
+
+        Ok(())
+    }
+
+    fn save_snapshot(&mut self, snapshot: &str) -> Result<(), StorageError> {
+        self.snapshot = Some(snapshot.to_owned());
+        self.log.clear();
+
+        Ok(())
+    }
+}
+
+impl FileStorage {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<FileStorage, StorageError> {
+        try!(fs::create_dir_all(dir.as_ref()));
+
+        Ok(FileStorage {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.dir.join("snapshot.json")
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.join("log.json")
+    }
+
+    fn read_file(path: &Path) -> Result<Option<Vec<u8>>, StorageError> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(StorageError::Io(e)),
+        };
+
+        let mut contents = Vec::new();
+        try!(file.read_to_end(&mut contents));
+
+        Ok(Some(contents))
+    }
+
+    fn to_string(contents: Vec<u8>) -> Result<String, StorageError> {
+        String::from_utf8(contents)
+            .map_err(|e| StorageError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    // Entries are written with their newline last, so a log that doesn't end
+    // in one holds an append cut short by a crash. Everything after the last
+    // newline is ignored when the log is read and cut off before the next
+    // append.
+    fn complete_entries(contents: &[u8]) -> &[u8] {
+        match contents.iter().rposition(|&b| b == b'\n') {
+            Some(end) => &contents[..end + 1],
+            None => &[],
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn load_snapshot(&self) -> Result<Option<String>, StorageError> {
+        match try!(FileStorage::read_file(&self.snapshot_path())) {
+            Some(contents) => FileStorage::to_string(contents).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn load_log(&self) -> Result<Vec<String>, StorageError> {
+        let contents = match try!(FileStorage::read_file(&self.log_path())) {
+            Some(contents) => contents,
+            None => return Ok(Vec::new()),
+        };
+
+        let complete = try!(FileStorage::to_string(FileStorage::complete_entries(&contents).to_vec()));
+
+        Ok(complete.lines().filter(|l| !l.is_empty()).map(|l| l.to_owned()).collect())
+    }
+
+    fn append_log(&mut self, entry: &str) -> Result<(), StorageError> {
+        let mut file = try!(OpenOptions::new().create(true).read(true).write(true).open(self.log_path()));
+        let len = try!(file.seek(SeekFrom::End(0)));
+
+        if len > 0 {
+            let mut last = [0; 1];
+
+            try!(file.seek(SeekFrom::End(-1)));
+            try!(file.read_exact(&mut last));
+
+            if last[0] != b'\n' {
+                let mut contents = Vec::new();
+
+                try!(file.seek(SeekFrom::Start(0)));
+                try!(file.read_to_end(&mut contents));
+                try!(file.set_len(FileStorage::complete_entries(&contents).len() as u64));
+                try!(file.seek(SeekFrom::End(0)));
+            }
+        }
+
+        try!(writeln!(file, "{}", entry));
+        try!(file.sync_data());
+
+        Ok(())
+    }
+
+    // The snapshot is written next to the old one and renamed over it. A
+    // crash before the rename leaves the old snapshot and log; a crash after
+    // it can leave log entries the new snapshot already covers.
+    fn save_snapshot(&mut self, snapshot: &str) -> Result<(), StorageError> {
+        let tmp_path = self.dir.join("snapshot.json.tmp");
+
+        {
+            let mut file = try!(File::create(&tmp_path));
+            try!(file.write_all(snapshot.as_bytes()));
+            try!(file.sync_all());
+        }
+
+        try!(fs::rename(&tmp_path, self.snapshot_path()));
+
+        #[cfg(unix)]
+        try!(File::open(&self.dir).and_then(|dir| dir.sync_all()));
+
+        try!(File::create(self.log_path()));
+
+        Ok(())
+    }
+}
+
+impl<T, S> Persistent<T, S>
+    where T: OperationRDT + Encodable + Decodable,
+          T::Operation: Encodable + Decodable,
+          S: Storage
+{
+    // A storage without a snapshot gets one right away, so the value can
+    // always be rebuilt from storage alone.
+    pub fn open<F>(storage: S, init: F) -> Result<Persistent<T, S>, StorageError>
+        where F: FnOnce() -> T
+    {
+        let has_snapshot = try!(storage.load_snapshot()).is_some();
+        let (value, seq, log_len) = try!(Persistent::<T, S>::load(&storage, || Ok(init())));
+
+        let mut persistent = Persistent {
+            value: value,
+            storage: storage,
+            seq: seq,
+            log_len: log_len,
+            compact_after: 1000,
+        };
+
+        if !has_snapshot {
+            try!(persistent.compact());
+        }
+
+        Ok(persistent)
+    }
+
+    pub fn compact_after(mut self, entries: usize) -> Persistent<T, S> {
+        self.compact_after = entries;
+        self
+    }
+
+    pub fn value<'a>(&'a self) -> &'a T {
+        &self.value
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    // If the operation can't be logged, the value is rebuilt from storage
+    // so that it doesn't hold a change that was never recorded.
+    pub fn update<F>(&mut self, update_fn: F) -> Result<Option<T::Operation>, StorageError>
+        where F: FnOnce(&mut T) -> Option<T::Operation>
+    {
+        let op = update_fn(&mut self.value);
+
+        if let Some(ref op) = op {
+            if let Err(e) = self.append(op) {
+                try!(self.reload());
+
+                return Err(e);
+            }
+
+            self.compact_if_needed();
+        }
+
+        Ok(op)
+    }
+
+    pub fn apply(&mut self, op: &T::Operation) -> Result<(), StorageError> {
+        try!(self.append(op));
+
+        self.value.apply(op);
+        self.compact_if_needed();
+
+        Ok(())
+    }
+
+    pub fn compact(&mut self) -> Result<(), StorageError> {
+        let snapshot = try!(json::encode(&(self.seq, &self.value)));
+
+        try!(self.storage.save_snapshot(&snapshot));
+        self.log_len = 0;
+
+        Ok(())
+    }
+
+    fn load<F>(storage: &S, init: F) -> Result<(T, u64, usize), StorageError>
+        where F: FnOnce() -> Result<T, StorageError>
+    {
+        let (mut seq, mut value): (u64, T) = match try!(storage.load_snapshot()) {
+            Some(snapshot) => try!(json::decode(&snapshot)),
+            None => (0, try!(init())),
+        };
+
+        let mut log_len = 0;
+
+        for entry in try!(storage.load_log()) {
+            let (entry_seq, op): (u64, T::Operation) = try!(json::decode(&entry));
+
+            if entry_seq > seq {
+                value.apply(&op);
+                seq = entry_seq;
+                log_len += 1;
+            }
+        }
+
+        Ok((value, seq, log_len))
+    }
+
+    fn reload(&mut self) -> Result<(), StorageError> {
+        let (value, seq, log_len) = try!(Persistent::<T, S>::load(&self.storage, || {
+            Err(StorageError::Io(io::Error::new(io::ErrorKind::NotFound, "snapshot is missing")))
+        }));
+
+        self.value = value;
+        self.seq = seq;
+        self.log_len = log_len;
+
+        Ok(())
+    }
+
+    fn append(&mut self, op: &T::Operation) -> Result<(), StorageError> {
+        let entry = try!(json::encode(&(self.seq + 1, op)));
+
+        try!(self.storage.append_log(&entry));
+        self.seq += 1;
+        self.log_len += 1;
+
+        Ok(())
+    }
+
+    // The operation is already in the log, so a failed compaction only
+    // leaves a longer log; it is tried again after the next entry.
+    fn compact_if_needed(&mut self) {
+        if self.log_len >= self.compact_after {
+            let _ = self.compact();
+        }
+    }
+}
+
+impl<T, S> Persistent<T, S>
+    where T: OperationRDT + StateRDT + Encodable + Decodable,
+          T::Operation: Encodable + Decodable,
+          S: Storage
+{
+    // Merged state has no operation to log, so a merge always writes a
+    // fresh snapshot. If that fails, the value is rebuilt from storage like
+    // it is for a failed update.
+    pub fn merge(&mut self, other: &T) -> Result<(), StorageError> {
+        self.value.merge(other);
+
+        if let Err(e) = self.compact() {
+            try!(self.reload());
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Persistent, MemoryStorage, FileStorage, Storage, StorageError};
+
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::iter::FromIterator;
+
+    use uuid::Uuid;
+
+    use counters::PNCounter;
+    use sets::{GSet, ObserveRemoveSet};
+
+    struct FailingStorage {
+        inner: MemoryStorage,
+        fail: bool,
+    }
+
+    impl Storage for FailingStorage {
+        fn load_snapshot(&self) -> Result<Option<String>, StorageError> {
+            self.inner.load_snapshot()
+        }
+
+        fn load_log(&self) -> Result<Vec<String>, StorageError> {
+            self.inner.load_log()
+        }
+
+        fn append_log(&mut self, entry: &str) -> Result<(), StorageError> {
+            if self.fail {
+                return Err(StorageError::Io(io::Error::new(io::ErrorKind::Other, "disk full")));
+            }
+
+            self.inner.append_log(entry)
+        }
+
+        fn save_snapshot(&mut self, snapshot: &str) -> Result<(), StorageError> {
+            if self.fail {
+                return Err(StorageError::Io(io::Error::new(io::ErrorKind::Other, "disk full")));
+            }
+
+            self.inner.save_snapshot(snapshot)
+        }
+    }
+
+    #[test]
+    fn reopen_memory_storage() {
+        let mut set = Persistent::open(MemoryStorage::new(), || ObserveRemoveSet::new()).unwrap();
+
+        set.update(|s| Some(s.add("a".to_owned()))).unwrap();
+        set.update(|s| Some(s.add("b".to_owned()))).unwrap();
+        set.update(|s| s.remove("a".to_owned())).unwrap();
+
+        let storage = set.into_storage();
+        let set: Persistent<ObserveRemoveSet<String>, _> =
+            Persistent::open(storage, || ObserveRemoveSet::new()).unwrap();
+
+        assert_eq!(set.value().value(), HashSet::from_iter(vec!["b".to_owned()]));
+    }
+
+    #[test]
+    fn compact_memory_storage() {
+        let mut counter = Persistent::open(MemoryStorage::new(), || PNCounter::new("h1".to_owned()))
+            .unwrap()
+            .compact_after(2);
+
+        counter.update(|c| c.add(5)).unwrap();
+        counter.update(|c| c.add(-2)).unwrap();
+        counter.update(|c| c.add(10)).unwrap();
+
+        let storage = counter.into_storage();
+
+        assert!(storage.load_snapshot().unwrap().is_some());
+        assert_eq!(storage.load_log().unwrap().len(), 1);
+
+        let counter: Persistent<PNCounter<String, i64>, _> =
+            Persistent::open(storage, || PNCounter::new("h1".to_owned())).unwrap();
+
+        assert_eq!(counter.value().value(), 13);
+    }
+
+    #[test]
+    fn skip_log_entries_covered_by_snapshot() {
+        let mut set = Persistent::open(MemoryStorage::new(), || ObserveRemoveSet::new()).unwrap();
+
+        set.update(|s| Some(s.add("a".to_owned()))).unwrap();
+        set.update(|s| s.remove("a".to_owned())).unwrap();
+
+        let log = set.storage.log.clone();
+        set.compact().unwrap();
+        set.storage.log = log;
+
+        let set: Persistent<ObserveRemoveSet<String>, _> =
+            Persistent::open(set.into_storage(), || ObserveRemoveSet::new()).unwrap();
+
+        assert_eq!(set.log_len, 0);
+        assert_eq!(set.seq, 2);
+        assert!(set.value().value().is_empty());
+    }
+
+    #[test]
+    fn keep_value_when_log_append_fails() {
+        let storage = FailingStorage { inner: MemoryStorage::new(), fail: false };
+        let mut set = Persistent::open(storage, || ObserveRemoveSet::new()).unwrap();
+
+        set.update(|s| Some(s.add("a".to_owned()))).unwrap();
+        set.storage.fail = true;
+
+        assert!(set.update(|s| Some(s.add("b".to_owned()))).is_err());
+        assert!(set.apply(&ObserveRemoveSet::new().add("c".to_owned())).is_err());
+        assert_eq!(set.value().value(), HashSet::from_iter(vec!["a".to_owned()]));
+
+        set.storage.fail = false;
+        set.update(|s| Some(s.add("d".to_owned()))).unwrap();
+
+        let set: Persistent<ObserveRemoveSet<String>, _> =
+            Persistent::open(set.into_storage(), || ObserveRemoveSet::new()).unwrap();
+
+        assert_eq!(set.value().value(), HashSet::from_iter(vec!["a".to_owned(), "d".to_owned()]));
+    }
+
+    #[test]
+    fn keep_value_when_merge_snapshot_fails() {
+        let storage = FailingStorage { inner: MemoryStorage::new(), fail: false };
+        let mut set = Persistent::open(storage, || ObserveRemoveSet::new()).unwrap();
+        let mut other = ObserveRemoveSet::new();
+
+        set.update(|s| Some(s.add("a".to_owned()))).unwrap();
+        other.add("b".to_owned());
+        set.storage.fail = true;
+
+        assert!(set.merge(&other).is_err());
+        assert_eq!(set.value().value(), HashSet::from_iter(vec!["a".to_owned()]));
+
+        set.storage.fail = false;
+        set.merge(&other).unwrap();
+
+        assert_eq!(set.value().value(), HashSet::from_iter(vec!["a".to_owned(), "b".to_owned()]));
+    }
+
+    #[test]
+    fn reopen_file_storage_after_torn_append() {
+        let dir = env::temp_dir().join(format!("crdt-storage-{}", Uuid::new_v4()));
+
+        {
+            let mut set = Persistent::open(FileStorage::open(&dir).unwrap(), || GSet::new()).unwrap();
+
+            set.update(|s| s.add(1)).unwrap();
+        }
+
+        let mut log = OpenOptions::new().append(true).open(dir.join("log.json")).unwrap();
+        log.write_all(b"[2,{\"Add\":").unwrap();
+
+        {
+            let mut set: Persistent<GSet<i32>, _> =
+                Persistent::open(FileStorage::open(&dir).unwrap(), || GSet::new()).unwrap();
+
+            assert_eq!(*set.value().value(), HashSet::from_iter(vec![1]));
+
+            set.update(|s| s.add(3)).unwrap();
+        }
+
+        let set: Persistent<GSet<i32>, _> =
+            Persistent::open(FileStorage::open(&dir).unwrap(), || GSet::new()).unwrap();
+
+        assert_eq!(*set.value().value(), HashSet::from_iter(vec![1, 3]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reopen_file_storage() {
+        let dir = env::temp_dir().join(format!("crdt-storage-{}", Uuid::new_v4()));
+
+        {
+            let mut remote = GSet::new();
+            let op = remote.add(3).unwrap();
+
+            let mut set = Persistent::open(FileStorage::open(&dir).unwrap(), || GSet::new()).unwrap();
+
+            set.update(|s| s.add(1)).unwrap();
+            set.compact().unwrap();
+            set.update(|s| s.add(2)).unwrap();
+            set.apply(&op).unwrap();
+        }
+
+        let set: Persistent<GSet<i32>, _> =
+            Persistent::open(FileStorage::open(&dir).unwrap(), || GSet::new()).unwrap();
+
+        assert_eq!(*set.value().value(), HashSet::from_iter(vec![1, 2, 3]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
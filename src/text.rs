@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+
+use clock::{LamportClock, LamportTimestamp};
+use core::{StateRDT, OperationRDT, DiffRDT};
+use lists::{RGAList, RGAListOperation};
+use merkle::{MerkleTree, item_hash, in_buckets};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, RustcEncodable, RustcDecodable)]
+pub enum MarkType {
+    Bold,
+    Italic,
+    Link,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+pub enum Mark {
+    Bold,
+    Italic,
+    Link(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub marks: Vec<Mark>,
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+enum Anchor {
+    Before(LamportTimestamp),
+    After(LamportTimestamp),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+enum MarkAction {
+    Add(Mark),
+    Remove(MarkType),
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MarkOperation {
+    id: LamportTimestamp,
+    action: MarkAction,
+    start: Anchor,
+    end: Anchor,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum RichTextOperation {
+    Text(RGAListOperation<char>),
+    Mark(MarkOperation),
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct RichText {
+    text: RGAList<char>,
+    marks: Vec<MarkOperation>,
+    clock: LamportClock,
+}
+
+impl Mark {
+    pub fn mark_type(&self) -> MarkType {
+        match *self {
+            Mark::Bold => MarkType::Bold,
+            Mark::Italic => MarkType::Italic,
+            Mark::Link(_) => MarkType::Link,
+        }
+    }
+}
+
+impl MarkType {
+    // Formatting like bold grows when text is typed at the end of the span,
+    // while links stop at the last character they were applied to.
+    fn expands(&self) -> bool {
+        match *self {
+            MarkType::Bold | MarkType::Italic => true,
+            MarkType::Link => false,
+        }
+    }
+}
+
+impl MarkAction {
+    fn mark_type(&self) -> MarkType {
+        match *self {
+            MarkAction::Add(ref mark) => mark.mark_type(),
+            MarkAction::Remove(mark_type) => mark_type,
+        }
+    }
+}
+
+impl RichText {
+    pub fn new() -> RichText {
+        RichText {
+            text: RGAList::new(),
+            marks: Vec::new(),
+            clock: LamportClock::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().map(|(_, c)| *c).collect()
+    }
+
+    pub fn insert(&mut self, index: usize, text: &str) -> Vec<RichTextOperation> {
+        let mut ops = Vec::new();
+
+        for (i, c) in text.chars().enumerate() {
+            match self.text.insert(index + i, c) {
+                Some(op) => ops.push(RichTextOperation::Text(op)),
+                None => break,
+            }
+        }
+
+        ops
+    }
+
+    pub fn remove(&mut self, index: usize, len: usize) -> Vec<RichTextOperation> {
+        let mut ops = Vec::new();
+
+        for _ in 0..len {
+            match self.text.remove(index) {
+                Some(op) => ops.push(RichTextOperation::Text(op)),
+                None => break,
+            }
+        }
+
+        ops
+    }
+
+    pub fn add_mark(&mut self, start: usize, end: usize, mark: Mark) -> Option<RichTextOperation> {
+        self.mark(start, end, MarkAction::Add(mark))
+    }
+
+    pub fn remove_mark(&mut self, start: usize, end: usize, mark_type: MarkType)
+        -> Option<RichTextOperation>
+    {
+        self.mark(start, end, MarkAction::Remove(mark_type))
+    }
+
+    pub fn spans(&self) -> Vec<Span> {
+        let positions = self.text.entries()
+            .enumerate()
+            .map(|(index, (id, _, _))| (id.clone(), index))
+            .collect::<HashMap<_, _>>();
+
+        let mut spans: Vec<Span> = Vec::new();
+
+        for (index, (_, c, deleted)) in self.text.entries().enumerate() {
+            if deleted {
+                continue;
+            }
+
+            let marks = self.marks_at(&positions, 2 * index + 1);
+
+            let extend = match spans.last() {
+                Some(span) => span.marks == marks,
+                None => false,
+            };
+
+            if extend {
+                spans.last_mut().unwrap().text.push(*c);
+            }
+            else {
+                spans.push(Span { text: c.to_string(), marks: marks });
+            }
+        }
+
+        spans
+    }
+
+    fn mark(&mut self, start: usize, end: usize, action: MarkAction) -> Option<RichTextOperation> {
+        if start >= end || end > self.text.len() {
+            return None;
+        }
+
+        let start_anchor = Anchor::Before(self.text.id_at(start).unwrap());
+        let end_anchor = if action.mark_type().expands() {
+            match self.text.id_at(end) {
+                Some(id) => Anchor::Before(id),
+                None => Anchor::End,
+            }
+        }
+        else {
+            Anchor::After(self.text.id_at(end - 1).unwrap())
+        };
+
+        let op = RichTextOperation::Mark(MarkOperation {
+            id: self.clock.tick(),
+            action: action,
+            start: start_anchor,
+            end: end_anchor,
+        });
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    // Anchors and characters are interleaved: the gap before the character
+    // at sequence index i is 2i, the character itself 2i + 1. Marks can
+    // arrive before the characters they are anchored to, which gives `None`.
+    fn anchor_position(positions: &HashMap<LamportTimestamp, usize>, anchor: &Anchor)
+        -> Option<usize>
+    {
+        match *anchor {
+            Anchor::Before(ref id) => positions.get(id).map(|p| 2 * p),
+            Anchor::After(ref id) => positions.get(id).map(|p| 2 * p + 2),
+            Anchor::End => Some(2 * positions.len() + 1),
+        }
+    }
+
+    fn marks_at(&self, positions: &HashMap<LamportTimestamp, usize>, position: usize) -> Vec<Mark> {
+        let mut winners: HashMap<MarkType, &MarkOperation> = HashMap::new();
+
+        for op in &self.marks {
+            let (start, end) = match (RichText::anchor_position(positions, &op.start),
+                                      RichText::anchor_position(positions, &op.end)) {
+                (Some(start), Some(end)) => (start, end),
+                _ => continue,
+            };
+
+            if position < start || position >= end {
+                continue;
+            }
+
+            let winner = winners.entry(op.action.mark_type()).or_insert(op);
+
+            if op.id > winner.id {
+                *winner = op;
+            }
+        }
+
+        let mut marks = winners.values()
+            .filter_map(|op| match op.action {
+                MarkAction::Add(ref mark) => Some(mark.clone()),
+                MarkAction::Remove(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        marks.sort_by_key(|m| m.mark_type());
+
+        marks
+    }
+}
+
+impl OperationRDT for RichText {
+    type Operation = RichTextOperation;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        match op {
+            &RichTextOperation::Text(ref op) => self.text.apply(op),
+            &RichTextOperation::Mark(ref op) => {
+                if self.marks.iter().any(|m| m.id == op.id) {
+                    return;
+                }
+
+                self.clock.observe(&op.id);
+                self.marks.push(op.clone());
+            },
+        }
+    }
+}
+
+impl StateRDT for RichText {
+    fn merge(&mut self, other: &Self) {
+        self.text.merge(&other.text);
+
+        for op in &other.marks {
+            self.apply(&RichTextOperation::Mark(op.clone()));
+        }
+    }
+}
+
+impl DiffRDT for RichText {
+    type Summary = (MerkleTree, MerkleTree);
+    type Delta = Vec<RichTextOperation>;
+
+    fn summary(&self) -> Self::Summary {
+        (self.text.summary(), self.marks.iter().map(|m| item_hash(&m.id)).collect())
+    }
+
+    // Text comes first so that every mark finds the characters it is
+    // anchored to.
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().1.diff(&summary.1);
+
+        let text = self.text.diff(&summary.0).into_iter().map(RichTextOperation::Text);
+        let marks = self.marks.iter()
+            .filter(|m| in_buckets(&buckets, item_hash(&m.id)))
+            .map(|m| RichTextOperation::Mark(m.clone()));
+
+        text.chain(marks).collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        for op in delta {
+            self.apply(op);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RichText, Mark, MarkType, Span};
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    fn span(text: &str, marks: Vec<Mark>) -> Span {
+        Span { text: text.to_owned(), marks: marks }
+    }
+
+    #[test]
+    fn make_rich_text() {
+        let text = RichText::new();
+
+        assert_eq!(text.text(), "");
+        assert!(text.spans().is_empty());
+    }
+
+    #[test]
+    fn add_and_remove_marks() {
+        let mut text = RichText::new();
+
+        text.insert(0, "hello world");
+        text.add_mark(0, 5, Mark::Bold).unwrap();
+        text.add_mark(3, 8, Mark::Italic).unwrap();
+        text.remove_mark(0, 2, MarkType::Bold).unwrap();
+
+        assert!(text.add_mark(4, 4, Mark::Bold).is_none());
+        assert_eq!(text.spans(), vec![
+            span("he", vec![]),
+            span("l", vec![Mark::Bold]),
+            span("lo", vec![Mark::Bold, Mark::Italic]),
+            span(" wo", vec![Mark::Italic]),
+            span("rld", vec![]),
+        ]);
+    }
+
+    #[test]
+    fn bold_expands_link_does_not() {
+        let mut text = RichText::new();
+
+        text.insert(0, "ab cd");
+        text.add_mark(0, 2, Mark::Bold).unwrap();
+        text.add_mark(3, 5, Mark::Link("http://x".to_owned())).unwrap();
+
+        text.insert(2, "!");
+        text.insert(6, "?");
+        text.insert(0, "_");
+
+        assert_eq!(text.text(), "_ab! cd?");
+        assert_eq!(text.spans(), vec![
+            span("_", vec![]),
+            span("ab!", vec![Mark::Bold]),
+            span(" ", vec![]),
+            span("cd", vec![Mark::Link("http://x".to_owned())]),
+            span("?", vec![]),
+        ]);
+    }
+
+    #[test]
+    fn apply_concurrent_insert_at_mark_boundary() {
+        let mut t1 = RichText::new();
+        let mut t2 = RichText::new();
+
+        for op in t1.insert(0, "abc") {
+            t2.apply(&op);
+        }
+
+        let op1 = t1.add_mark(0, 3, Mark::Bold).unwrap();
+        let ops2 = t2.insert(3, "d");
+
+        t2.apply(&op1);
+        for op in &ops2 {
+            t1.apply(op);
+        }
+
+        assert_eq!(t1.spans(), vec![span("abcd", vec![Mark::Bold])]);
+        assert_eq!(t2.spans(), t1.spans());
+    }
+
+    #[test]
+    fn apply_mark_before_its_text() {
+        let mut t1 = RichText::new();
+        let mut t2 = RichText::new();
+
+        let text_ops = t1.insert(0, "abc");
+        let mark_op = t1.add_mark(1, 2, Mark::Bold).unwrap();
+
+        t2.apply(&text_ops[0]);
+        t2.apply(&mark_op);
+
+        assert_eq!(t2.spans(), vec![span("a", vec![])]);
+
+        for op in &text_ops[1..] {
+            t2.apply(op);
+        }
+
+        assert_eq!(t2.spans(), t1.spans());
+    }
+
+    #[test]
+    fn merge_rich_text_state() {
+        let mut t1 = RichText::new();
+        let mut t2 = RichText::new();
+
+        t1.insert(0, "abcd");
+        t2.merge(&t1);
+
+        t1.add_mark(0, 4, Mark::Italic).unwrap();
+        t2.remove(1, 2);
+        t2.add_mark(0, 2, Mark::Bold).unwrap();
+
+        t1.merge(&t2);
+        t2.merge(&t1);
+
+        let expected = vec![
+            span("ad", vec![Mark::Bold, Mark::Italic]),
+        ];
+
+        assert_eq!(t1.spans(), expected);
+        assert_eq!(t2.spans(), expected);
+    }
+
+    #[test]
+    fn diff_rich_text_state() {
+        let mut t1 = RichText::new();
+        let mut t2 = RichText::new();
+
+        t1.insert(0, "abcd");
+        t2.merge(&t1);
+
+        t2.insert(4, "ef");
+        t2.add_mark(3, 6, Mark::Bold).unwrap();
+
+        t1.apply_diff(&t2.diff(&t1.summary()));
+
+        assert_eq!(t1.text(), "abcdef");
+        assert_eq!(t1.spans(), t2.spans());
+    }
+}
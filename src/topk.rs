@@ -0,0 +1,472 @@
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+use uuid::Uuid;
+
+use core::{StateRDT, OperationRDT, DiffRDT};
+use merkle::{Digest, item_hash};
+
+// An add is identified by its replica's id and sequence number.
+pub type Dot = (Uuid, u64);
+
+// Each add supersedes the dots it observed for its key, so a key holds more
+// than one dot only while adds to it are concurrent. `seen` and `gaps`
+// record every dot delivered and take the place of tombstones: a dot that
+// has been seen but is no longer in `entries` was superseded or removed.
+// `gaps` only holds dots delivered ahead of their predecessors.
+//
+// An add that pushes keys out of the top K removes them as well, so the
+// state holds K keys plus those added concurrently since the last local add.
+// A key dropped this way does not come back when a key above it is removed.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct TopK<K: Hash + Eq + Ord + Clone, S: Ord + Clone> {
+    id: Uuid,
+    size: usize,
+    entries: HashMap<K, Vec<(Dot, S)>>,
+    index: BTreeMap<S, BTreeSet<K>>,
+    seen: HashMap<Uuid, u64>,
+    gaps: HashSet<Dot>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum TopKOperation<K, S> {
+    Add(K, S, Dot, Vec<(K, Vec<Dot>)>),
+    Remove(K, Vec<Dot>),
+}
+
+impl<K, S> TopK<K, S>
+    where K: Hash + Eq + Ord + Clone,
+          S: Ord + Clone
+{
+    pub fn new(size: usize) -> TopK<K, S> {
+        TopK {
+            id: Uuid::new_v4(),
+            size: size,
+            entries: HashMap::new(),
+            index: BTreeMap::new(),
+            seen: HashMap::new(),
+            gaps: HashSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        cmp::min(self.entries.len(), self.size)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&S> {
+        self.ranked().take(self.size).find(|&(k, _)| k == key).map(|(_, score)| score)
+    }
+
+    // Reads the K highest keys off the score index.
+    pub fn entries(&self) -> Vec<(K, S)> {
+        self.ranked()
+            .take(self.size)
+            .map(|(key, score)| (key.clone(), score.clone()))
+            .collect()
+    }
+
+    pub fn add(&mut self, key: K, score: S) -> Option<TopKOperation<K, S>> {
+        if self.score(&key).map_or(false, |current| *current >= score) {
+            return None;
+        }
+
+        if self.size == 0 {
+            return None;
+        }
+
+        if self.get(&key).is_none() {
+            let lowest = self.ranked().nth(self.size - 1);
+
+            if let Some((lowest_key, lowest_score)) = lowest {
+                if score < *lowest_score || (score == *lowest_score && key > *lowest_key) {
+                    return None;
+                }
+            }
+        }
+
+        let dot = (self.id.clone(), self.seen.get(&self.id).cloned().unwrap_or(0) + 1);
+
+        let mut removed = vec![(key.clone(), self.dots(&key))];
+
+        removed.extend(self.ranked()
+                       .filter(|&(k, _)| *k != key)
+                       .skip(self.size - 1)
+                       .map(|(k, _)| (k.clone(), self.dots(k))));
+
+        let op = TopKOperation::Add(key, score, dot, removed);
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<TopKOperation<K, S>> {
+        if !self.entries.contains_key(&key) {
+            return None;
+        }
+
+        let op = TopKOperation::Remove(key.clone(), self.dots(&key));
+
+        self.apply(&op);
+
+        Some(op)
+    }
+
+    // Highest score first, ties broken by key.
+    fn ranked<'a>(&'a self) -> Box<Iterator<Item=(&'a K, &'a S)> + 'a> {
+        Box::new(self.index.iter().rev().flat_map(|(score, keys)| keys.iter().map(move |key| (key, score))))
+    }
+
+    fn score(&self, key: &K) -> Option<&S> {
+        self.entries.get(key).and_then(|dots| dots.iter().map(|&(_, ref score)| score).max())
+    }
+
+    fn dots(&self, key: &K) -> Vec<Dot> {
+        self.entries.get(key).map_or(Vec::new(), |dots| dots.iter().map(|&(ref dot, _)| dot.clone()).collect())
+    }
+
+    fn holds(&self, key: &K, dot: &Dot) -> bool {
+        self.entries.get(key).map_or(false, |dots| dots.iter().any(|&(ref d, _)| d == dot))
+    }
+
+    fn has_seen(&self, dot: &Dot) -> bool {
+        dot.1 <= self.seen.get(&dot.0).cloned().unwrap_or(0) || self.gaps.contains(dot)
+    }
+
+    fn observe(&mut self, dot: &Dot) {
+        if !self.has_seen(dot) {
+            self.gaps.insert(dot.clone());
+            self.close_gaps(&dot.0);
+        }
+    }
+
+    fn observe_up_to(&mut self, id: &Uuid, counter: u64) {
+        if counter > self.seen.get(id).cloned().unwrap_or(0) {
+            self.seen.insert(id.clone(), counter);
+            self.gaps.retain(|&(ref i, c)| i != id || c > counter);
+            self.close_gaps(id);
+        }
+    }
+
+    fn close_gaps(&mut self, id: &Uuid) {
+        loop {
+            let next = (id.clone(), self.seen.get(id).cloned().unwrap_or(0) + 1);
+
+            if !self.gaps.remove(&next) {
+                break;
+            }
+
+            self.seen.insert(next.0, next.1);
+        }
+    }
+
+    fn insert_dot(&mut self, key: &K, dot: &Dot, score: &S) {
+        if self.has_seen(dot) {
+            return;
+        }
+
+        self.observe(dot);
+
+        let old = self.score(key).cloned();
+        self.entries.entry(key.clone()).or_insert(Vec::new()).push((dot.clone(), score.clone()));
+        self.reindex(key, old);
+    }
+
+    fn remove_dots(&mut self, key: &K, dots: &[Dot]) {
+        for dot in dots {
+            self.observe(dot);
+        }
+
+        let old = self.score(key).cloned();
+
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.retain(|&(ref dot, _)| !dots.contains(dot));
+        }
+
+        self.reindex(key, old);
+    }
+
+    fn reindex(&mut self, key: &K, old: Option<S>) {
+        if self.entries.get(key).map_or(false, |dots| dots.is_empty()) {
+            self.entries.remove(key);
+        }
+
+        let new = self.score(key).cloned();
+
+        if old == new {
+            return;
+        }
+
+        if let Some(old) = old {
+            let empty = match self.index.get_mut(&old) {
+                Some(keys) => {
+                    keys.remove(key);
+                    keys.is_empty()
+                },
+                None => false,
+            };
+
+            if empty {
+                self.index.remove(&old);
+            }
+        }
+
+        if let Some(new) = new {
+            self.index.entry(new).or_insert(BTreeSet::new()).insert(key.clone());
+        }
+    }
+}
+
+impl<K, S> OperationRDT for TopK<K, S>
+    where K: Hash + Eq + Ord + Clone,
+          S: Ord + Clone
+{
+    type Operation = TopKOperation<K, S>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        match op {
+            &TopKOperation::Add(ref key, ref score, ref dot, ref removed) => {
+                for &(ref key, ref dots) in removed {
+                    self.remove_dots(key, dots);
+                }
+
+                self.insert_dot(key, dot, score);
+            },
+            &TopKOperation::Remove(ref key, ref dots) => {
+                self.remove_dots(key, dots);
+            },
+        }
+    }
+}
+
+impl<K, S> StateRDT for TopK<K, S>
+    where K: Hash + Eq + Ord + Clone,
+          S: Ord + Clone
+{
+    // Dots the other side has seen but no longer holds were superseded or
+    // removed there.
+    fn merge(&mut self, other: &Self) {
+        let dropped = self.entries.iter()
+            .map(|(key, dots)| {
+                let dots = dots.iter()
+                    .map(|&(ref dot, _)| dot.clone())
+                    .filter(|dot| other.has_seen(dot) && !other.holds(key, dot))
+                    .collect::<Vec<_>>();
+
+                (key.clone(), dots)
+            })
+            .collect::<Vec<_>>();
+
+        for (key, dots) in dropped {
+            self.remove_dots(&key, &dots);
+        }
+
+        for (key, dots) in &other.entries {
+            for &(ref dot, ref score) in dots {
+                self.insert_dot(key, dot, score);
+            }
+        }
+
+        for (id, &counter) in &other.seen {
+            self.observe_up_to(id, counter);
+        }
+
+        for dot in &other.gaps {
+            self.observe(dot);
+        }
+    }
+}
+
+// The state is bounded to about K keys, so a peer whose digest differs is
+// sent all of it.
+impl<K, S> DiffRDT for TopK<K, S>
+    where K: Hash + Eq + Ord + Clone,
+          S: Ord + Clone
+{
+    type Summary = u64;
+    type Delta = Option<TopK<K, S>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.digest()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        if self.digest() == *summary {
+            None
+        }
+        else {
+            Some(self.clone())
+        }
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        if let Some(ref other) = *delta {
+            self.merge(other);
+        }
+    }
+}
+
+// A dot's score never changes, so the dots and the causal context are
+// enough to tell states apart.
+impl<K, S> Digest for TopK<K, S>
+    where K: Hash + Eq + Ord + Clone,
+          S: Ord + Clone
+{
+    fn digest(&self) -> u64 {
+        let entries = self.entries.iter()
+            .flat_map(|(key, dots)| dots.iter().map(move |&(ref dot, _)| item_hash(&(key, dot))));
+        let seen = self.seen.iter().map(|entry| item_hash(&entry));
+        let gaps = self.gaps.iter().map(|dot| item_hash(&(dot, true)));
+
+        entries.chain(seen).chain(gaps).fold(0, |acc, hash| acc ^ hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TopK;
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+    use merkle::Digest;
+
+    #[test]
+    fn make_top_k() {
+        let top: TopK<&str, u32> = TopK::new(3);
+
+        assert_eq!(top.len(), 0);
+        assert!(top.entries().is_empty());
+    }
+
+    #[test]
+    fn add_top_k() {
+        let mut top = TopK::new(2);
+
+        top.add("alice", 10).unwrap();
+        top.add("bob", 20).unwrap();
+        top.add("alice", 30).unwrap();
+
+        assert!(top.add("alice", 5).is_none());
+        assert!(top.add("carol", 1).is_none());
+
+        top.add("carol", 25).unwrap();
+
+        assert_eq!(top.entries(), vec![("alice", 30), ("carol", 25)]);
+        assert_eq!(top.get(&"bob"), None);
+    }
+
+    #[test]
+    fn remove_top_k() {
+        let mut top = TopK::new(3);
+
+        top.add("alice", 10).unwrap();
+        top.add("bob", 20).unwrap();
+        top.remove("bob").unwrap();
+
+        assert!(top.remove("bob").is_none());
+        assert_eq!(top.entries(), vec![("alice", 10)]);
+
+        top.add("bob", 5).unwrap();
+
+        assert_eq!(top.entries(), vec![("alice", 10), ("bob", 5)]);
+    }
+
+    #[test]
+    fn apply_concurrent_add_and_remove() {
+        let mut t1 = TopK::new(3);
+        let mut t2 = TopK::new(3);
+
+        let op1 = t1.add("alice", 10).unwrap();
+        t2.apply(&op1);
+
+        let op2 = t1.remove("alice").unwrap();
+        let op3 = t2.add("alice", 15).unwrap();
+
+        t1.apply(&op3);
+        t2.apply(&op2);
+
+        assert_eq!(t1.entries(), vec![("alice", 15)]);
+        assert_eq!(t2.entries(), vec![("alice", 15)]);
+    }
+
+    #[test]
+    fn apply_top_k_ops_in_any_order() {
+        let mut t1 = TopK::new(1);
+        let mut t2 = TopK::new(1);
+        let mut t3 = TopK::new(1);
+
+        let op1 = t1.add("alice", 10).unwrap();
+        let op2 = t1.add("bob", 20).unwrap();
+        t2.apply(&op1);
+        t2.apply(&op2);
+
+        let op3 = t2.remove("bob").unwrap();
+
+        t3.apply(&op2);
+        t3.apply(&op1);
+        t3.apply(&op3);
+        t1.apply(&op3);
+
+        assert!(t1.entries().is_empty());
+        assert_eq!(t2.entries(), t1.entries());
+        assert_eq!(t3.entries(), t1.entries());
+        assert_eq!(t3.digest(), t1.digest());
+    }
+
+    #[test]
+    fn bound_top_k_state() {
+        let mut t1 = TopK::new(3);
+        let mut t2 = TopK::new(3);
+
+        for i in 0..100 {
+            let op1 = t1.add(i % 10, i).unwrap();
+            let op2 = t2.add(i % 7, i).unwrap();
+
+            t1.apply(&op2);
+            t2.apply(&op1);
+        }
+
+        for top in &[&t1, &t2] {
+            assert!(top.entries.len() <= 4);
+            assert!(top.entries.values().all(|dots| dots.len() <= 2));
+            assert!(top.gaps.is_empty());
+            assert_eq!(top.seen.len(), 2);
+        }
+
+        assert_eq!(t1.entries(), t2.entries());
+        assert_eq!(t1.entries(), vec![(1, 99), (9, 99), (0, 98)]);
+    }
+
+    #[test]
+    fn merge_top_k_state() {
+        let mut t1 = TopK::new(2);
+        let mut t2 = TopK::new(2);
+
+        t1.add("alice", 10).unwrap();
+        t1.add("bob", 40).unwrap();
+        t2.add("carol", 30).unwrap();
+        t2.add("alice", 20).unwrap();
+
+        t1.merge(&t2);
+        t2.merge(&t1);
+
+        assert_eq!(t1.entries(), vec![("bob", 40), ("carol", 30)]);
+        assert_eq!(t2.entries(), t1.entries());
+    }
+
+    #[test]
+    fn diff_top_k_state() {
+        let mut t1 = TopK::new(2);
+        let mut t2 = TopK::new(2);
+
+        t1.add("alice", 10).unwrap();
+        t2.add("carol", 30).unwrap();
+        t2.add("bob", 40).unwrap();
+
+        t1.apply_diff(&t2.diff(&t1.summary()));
+        t2.apply_diff(&t1.diff(&t2.summary()));
+
+        assert_eq!(t1.entries(), vec![("bob", 40), ("carol", 30)]);
+        assert_eq!(t2.entries(), t1.entries());
+    }
+}
@@ -0,0 +1,263 @@
+use std::cmp::Ordering;
+
+use uuid::Uuid;
+
+use clock::VersionVector;
+use core::{OperationRDT, Lattice};
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct AtomicOperation<Op> {
+    replica: Uuid,
+    seq: u64,
+    deps: VersionVector,
+    ops: Vec<Op>,
+}
+
+// The CRDTs updated together are registered as the fields of one struct;
+// `#[derive(OperationRDT)]` from crdt-derive generates its combined
+// operation type.
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub struct AtomicReplica<T: OperationRDT> where T::Operation: Clone {
+    id: Uuid,
+    value: T,
+    applied: VersionVector,
+    pending: Vec<AtomicOperation<T::Operation>>,
+}
+
+// Updates run against a staged copy of the value, which the replica only
+// takes in `commit`; a transaction dropped before then changes nothing.
+pub struct AtomicTransaction<'a, T: OperationRDT + 'a> where T::Operation: Clone {
+    replica: &'a mut AtomicReplica<T>,
+    staged: T,
+    ops: Vec<T::Operation>,
+}
+
+impl<Op> AtomicOperation<Op> {
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+}
+
+impl<T: OperationRDT> AtomicReplica<T> where T::Operation: Clone {
+    pub fn new(value: T) -> AtomicReplica<T> {
+        AtomicReplica {
+            id: Uuid::new_v4(),
+            value: value,
+            applied: VersionVector::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn value<'a>(&'a self) -> &'a T {
+        &self.value
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn transaction<'a>(&'a mut self) -> AtomicTransaction<'a, T> where T: Clone {
+        AtomicTransaction {
+            staged: self.value.clone(),
+            replica: self,
+            ops: Vec::new(),
+        }
+    }
+
+    // A transaction is ready once every transaction it depended on has been
+    // applied here, including the previous one from the same replica.
+    fn is_ready(&self, op: &AtomicOperation<T::Operation>) -> bool {
+        let ordered = match op.deps.compare(&self.applied) {
+            Some(Ordering::Less) | Some(Ordering::Equal) => true,
+            _ => false,
+        };
+
+        ordered && self.applied.get(&op.replica) + 1 == op.seq
+    }
+
+    fn apply_ready(&mut self) {
+        loop {
+            let ready = self.pending.iter().position(|op| self.is_ready(op));
+
+            match ready {
+                Some(index) => {
+                    let op = self.pending.remove(index);
+
+                    for inner in &op.ops {
+                        self.value.apply(inner);
+                    }
+
+                    self.applied.observe(&op.replica, op.seq);
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+impl<'a, T: OperationRDT> AtomicTransaction<'a, T> where T::Operation: Clone {
+    pub fn update<F>(&mut self, update_fn: F) -> &mut Self
+        where F: FnOnce(&mut T) -> Option<T::Operation>
+    {
+        if let Some(op) = update_fn(&mut self.staged) {
+            self.ops.push(op);
+        }
+
+        self
+    }
+
+    pub fn commit(self) -> Option<AtomicOperation<T::Operation>> {
+        if self.ops.is_empty() {
+            return None;
+        }
+
+        let deps = self.replica.applied.clone();
+        let id = self.replica.id.clone();
+        let seq = self.replica.applied.increment(&id);
+
+        for op in &self.ops {
+            self.replica.value.apply(op);
+        }
+
+        Some(AtomicOperation {
+            replica: id,
+            seq: seq,
+            deps: deps,
+            ops: self.ops,
+        })
+    }
+}
+
+impl<T: OperationRDT> OperationRDT for AtomicReplica<T> where T::Operation: Clone {
+    type Operation = AtomicOperation<T::Operation>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        if self.applied.contains(&op.replica, op.seq) ||
+            self.pending.iter().any(|p| p.replica == op.replica && p.seq == op.seq) {
+            return;
+        }
+
+        self.pending.push(op.clone());
+        self.apply_ready();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AtomicReplica, AtomicOperation};
+
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    use core::OperationRDT;
+    use counters::{PNCounter, SetPNCounterOperation};
+    use sets::{ObserveRemoveSet, ORSetOperation};
+
+    // What `#[derive(OperationRDT)]` generates, written out since the derive
+    // crate depends on this one.
+    #[derive(Clone)]
+    struct Team {
+        members: ObserveRemoveSet<&'static str>,
+        total: PNCounter<&'static str, i32>,
+    }
+
+    #[derive(Clone)]
+    enum TeamOperation {
+        Members(ORSetOperation<&'static str>),
+        Total(SetPNCounterOperation<&'static str, i32>),
+    }
+
+    impl Team {
+        fn new(id: &'static str) -> Team {
+            Team {
+                members: ObserveRemoveSet::new(),
+                total: PNCounter::new(id),
+            }
+        }
+    }
+
+    impl OperationRDT for Team {
+        type Operation = TeamOperation;
+
+        fn apply(&mut self, op: &TeamOperation) {
+            match op {
+                &TeamOperation::Members(ref op) => self.members.apply(op),
+                &TeamOperation::Total(ref op) => self.total.apply(op),
+            }
+        }
+    }
+
+    fn join(team: &mut AtomicReplica<Team>, name: &'static str, total: i32)
+        -> AtomicOperation<TeamOperation>
+    {
+        let mut tx = team.transaction();
+
+        tx.update(|t| Some(TeamOperation::Members(t.members.add(name))))
+            .update(|t| t.total.add(total).map(TeamOperation::Total));
+
+        tx.commit().unwrap()
+    }
+
+    #[test]
+    fn make_atomic_replica() {
+        let mut replica = AtomicReplica::new(Team::new("h1"));
+
+        assert_eq!(replica.pending(), 0);
+        assert!(replica.transaction().commit().is_none());
+    }
+
+    #[test]
+    fn apply_atomic_transaction() {
+        let mut r1 = AtomicReplica::new(Team::new("h1"));
+        let mut r2 = AtomicReplica::new(Team::new("h2"));
+
+        let op = join(&mut r1, "alice", 1);
+
+        r2.apply(&op);
+        r2.apply(&op);
+
+        assert_eq!(op.len(), 2);
+        assert_eq!(r2.value().members.value(), HashSet::from_iter(vec!["alice"]));
+        assert_eq!(r2.value().total.value(), 1);
+    }
+
+    #[test]
+    fn drop_uncommitted_transaction() {
+        let mut r1 = AtomicReplica::new(Team::new("h1"));
+
+        {
+            let mut tx = r1.transaction();
+
+            tx.update(|t| Some(TeamOperation::Members(t.members.add("alice"))));
+        }
+
+        let op = join(&mut r1, "bob", 1);
+
+        assert_eq!(op.seq, 1);
+        assert_eq!(r1.value().members.value(), HashSet::from_iter(vec!["bob"]));
+        assert_eq!(r1.value().total.value(), 1);
+    }
+
+    #[test]
+    fn buffer_transactions_until_dependencies_arrive() {
+        let mut r1 = AtomicReplica::new(Team::new("h1"));
+        let mut r2 = AtomicReplica::new(Team::new("h2"));
+        let mut r3 = AtomicReplica::new(Team::new("h3"));
+
+        let op1 = join(&mut r1, "alice", 1);
+        r2.apply(&op1);
+        let op2 = join(&mut r2, "bob", 1);
+
+        r3.apply(&op2);
+
+        assert_eq!(r3.pending(), 1);
+        assert!(r3.value().members.value().is_empty());
+        assert_eq!(r3.value().total.value(), 0);
+
+        r3.apply(&op1);
+
+        assert_eq!(r3.pending(), 0);
+        assert_eq!(r3.value().members.value(), HashSet::from_iter(vec!["alice", "bob"]));
+        assert_eq!(r3.value().total.value(), 2);
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use clock::{LamportClock, LamportTimestamp};
+use core::{StateRDT, OperationRDT, DiffRDT};
+use merkle::{MerkleTree, item_hash, in_buckets};
+
+#[derive(Debug, Clone, PartialEq, Eq, RustcEncodable, RustcDecodable)]
+enum TreeParent {
+    Root,
+    Trash,
+    Node(Uuid),
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MoveTreeOperation<M> {
+    timestamp: LamportTimestamp,
+    parent: TreeParent,
+    meta: M,
+    child: Uuid,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+struct LogEntry<M> {
+    op: MoveTreeOperation<M>,
+    old: Option<(TreeParent, M)>,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct MoveTree<M: Clone> {
+    clock: LamportClock,
+    log: Vec<LogEntry<M>>,
+    nodes: HashMap<Uuid, (TreeParent, M)>,
+}
+
+impl TreeParent {
+    fn from_option(parent: Option<Uuid>) -> TreeParent {
+        match parent {
+            Some(id) => TreeParent::Node(id),
+            None => TreeParent::Root,
+        }
+    }
+}
+
+impl<M> MoveTreeOperation<M> {
+    pub fn child(&self) -> Uuid {
+        self.child.clone()
+    }
+}
+
+impl<M: Clone> MoveTree<M> {
+    pub fn new() -> MoveTree<M> {
+        MoveTree {
+            clock: LamportClock::new(),
+            log: Vec::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        let mut current = id.clone();
+
+        loop {
+            match self.nodes.get(&current) {
+                Some(&(TreeParent::Root, _)) => return true,
+                Some(&(TreeParent::Node(ref parent), _)) => current = parent.clone(),
+                Some(&(TreeParent::Trash, _)) | None => return false,
+            }
+        }
+    }
+
+    pub fn parent(&self, id: &Uuid) -> Option<Option<Uuid>> {
+        if !self.contains(id) {
+            return None;
+        }
+
+        match self.nodes[id].0 {
+            TreeParent::Node(ref parent) => Some(Some(parent.clone())),
+            _ => Some(None),
+        }
+    }
+
+    pub fn meta(&self, id: &Uuid) -> Option<&M> {
+        if self.contains(id) {
+            self.nodes.get(id).map(|&(_, ref meta)| meta)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn children(&self, parent: Option<&Uuid>) -> Vec<Uuid> {
+        let parent = TreeParent::from_option(parent.cloned());
+
+        let mut children = self.nodes.iter()
+            .filter(|&(_, &(ref p, _))| *p == parent)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        children.sort();
+
+        children
+    }
+
+    pub fn create(&mut self, parent: Option<Uuid>, meta: M) -> Option<MoveTreeOperation<M>> {
+        if let Some(ref parent) = parent {
+            if !self.contains(parent) {
+                return None;
+            }
+        }
+
+        Some(self.make_op(TreeParent::from_option(parent), meta, Uuid::new_v4()))
+    }
+
+    pub fn move_node(&mut self, child: Uuid, parent: Option<Uuid>) -> Option<MoveTreeOperation<M>> {
+        let meta = match self.meta(&child) {
+            Some(meta) => meta.clone(),
+            None => return None,
+        };
+
+        if let Some(ref parent) = parent {
+            if !self.contains(parent) || self.is_ancestor(&child, &TreeParent::Node(parent.clone())) {
+                return None;
+            }
+        }
+
+        Some(self.make_op(TreeParent::from_option(parent), meta, child))
+    }
+
+    pub fn rename(&mut self, child: Uuid, meta: M) -> Option<MoveTreeOperation<M>> {
+        if !self.contains(&child) {
+            return None;
+        }
+
+        let parent = self.nodes[&child].0.clone();
+
+        Some(self.make_op(parent, meta, child))
+    }
+
+    pub fn delete(&mut self, child: Uuid) -> Option<MoveTreeOperation<M>> {
+        let meta = match self.meta(&child) {
+            Some(meta) => meta.clone(),
+            None => return None,
+        };
+
+        Some(self.make_op(TreeParent::Trash, meta, child))
+    }
+
+    fn make_op(&mut self, parent: TreeParent, meta: M, child: Uuid) -> MoveTreeOperation<M> {
+        let op = MoveTreeOperation {
+            timestamp: self.clock.tick(),
+            parent: parent,
+            meta: meta,
+            child: child,
+        };
+
+        self.apply(&op);
+
+        op
+    }
+
+    fn is_ancestor(&self, ancestor: &Uuid, node: &TreeParent) -> bool {
+        let mut current = node.clone();
+
+        while let TreeParent::Node(id) = current {
+            if id == *ancestor {
+                return true;
+            }
+
+            current = match self.nodes.get(&id) {
+                Some(&(ref parent, _)) => parent.clone(),
+                None => return false,
+            };
+        }
+
+        false
+    }
+
+    fn do_op(&mut self, op: MoveTreeOperation<M>) -> LogEntry<M> {
+        let old = self.nodes.get(&op.child).cloned();
+
+        // Moves that would make a node its own ancestor are recorded in the
+        // log but leave the tree untouched.
+        if !self.is_ancestor(&op.child, &op.parent) {
+            self.nodes.insert(op.child.clone(), (op.parent.clone(), op.meta.clone()));
+        }
+
+        LogEntry {
+            op: op,
+            old: old,
+        }
+    }
+
+    fn undo_op(&mut self, entry: &LogEntry<M>) {
+        match entry.old {
+            Some(ref old) => {
+                self.nodes.insert(entry.op.child.clone(), old.clone());
+            },
+            None => {
+                self.nodes.remove(&entry.op.child);
+            },
+        }
+    }
+}
+
+impl<M: Clone> OperationRDT for MoveTree<M> {
+    type Operation = MoveTreeOperation<M>;
+
+    fn apply(&mut self, op: &Self::Operation) {
+        if self.log.iter().any(|e| e.op.timestamp == op.timestamp) {
+            return;
+        }
+
+        self.clock.observe(&op.timestamp);
+
+        // Undo every logged move newer than this one, apply it in timestamp
+        // order, then redo the undone moves on top of it.
+        let mut undone = Vec::new();
+
+        while self.log.last().map_or(false, |e| e.op.timestamp > op.timestamp) {
+            let entry = self.log.pop().unwrap();
+
+            self.undo_op(&entry);
+            undone.push(entry.op);
+        }
+
+        let entry = self.do_op(op.clone());
+        self.log.push(entry);
+
+        while let Some(op) = undone.pop() {
+            let entry = self.do_op(op);
+            self.log.push(entry);
+        }
+    }
+}
+
+impl<M: Clone> StateRDT for MoveTree<M> {
+    fn merge(&mut self, other: &Self) {
+        for entry in &other.log {
+            self.apply(&entry.op);
+        }
+    }
+}
+
+impl<M: Clone> DiffRDT for MoveTree<M> {
+    type Summary = MerkleTree;
+    type Delta = Vec<MoveTreeOperation<M>>;
+
+    fn summary(&self) -> Self::Summary {
+        self.log.iter().map(|e| item_hash(&e.op.timestamp)).collect()
+    }
+
+    fn diff(&self, summary: &Self::Summary) -> Self::Delta {
+        let buckets = self.summary().diff(summary);
+
+        self.log.iter()
+            .filter(|e| in_buckets(&buckets, item_hash(&e.op.timestamp)))
+            .map(|e| e.op.clone())
+            .collect()
+    }
+
+    fn apply_diff(&mut self, delta: &Self::Delta) {
+        for op in delta {
+            self.apply(op);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MoveTree;
+
+    use core::{StateRDT, OperationRDT, DiffRDT};
+
+    #[test]
+    fn make_move_tree() {
+        let tree: MoveTree<&'static str> = MoveTree::new();
+
+        assert!(tree.children(None).is_empty());
+    }
+
+    #[test]
+    fn create_move_tree_nodes() {
+        let mut tree = MoveTree::new();
+
+        let a = tree.create(None, "a").unwrap().child();
+        let b = tree.create(Some(a), "b").unwrap().child();
+
+        assert_eq!(tree.children(None), vec![a]);
+        assert_eq!(tree.children(Some(&a)), vec![b]);
+        assert_eq!(tree.parent(&b), Some(Some(a)));
+        assert_eq!(tree.meta(&b), Some(&"b"));
+    }
+
+    #[test]
+    fn move_and_delete_move_tree_nodes() {
+        let mut tree = MoveTree::new();
+
+        let a = tree.create(None, "a").unwrap().child();
+        let b = tree.create(None, "b").unwrap().child();
+        let c = tree.create(Some(a), "c").unwrap().child();
+
+        tree.move_node(c, Some(b)).unwrap();
+        assert!(tree.move_node(b, Some(c)).is_none());
+
+        tree.delete(b).unwrap();
+
+        assert_eq!(tree.children(None), vec![a]);
+        assert!(!tree.contains(&c));
+        assert!(tree.create(Some(c), "d").is_none());
+    }
+
+    #[test]
+    fn rename_move_tree_node() {
+        let mut t1 = MoveTree::new();
+        let mut t2 = MoveTree::new();
+
+        let op1 = t1.create(None, "draft").unwrap();
+        let a = op1.child();
+        t2.apply(&op1);
+
+        let op2 = t2.rename(a, "final").unwrap();
+        t1.apply(&op2);
+
+        assert_eq!(t1.meta(&a), Some(&"final"));
+    }
+
+    #[test]
+    fn apply_concurrent_cyclic_moves() {
+        let mut t1 = MoveTree::new();
+        let mut t2 = MoveTree::new();
+
+        let op1 = t1.create(None, "a").unwrap();
+        let op2 = t1.create(None, "b").unwrap();
+        let (a, b) = (op1.child(), op2.child());
+
+        t2.apply(&op1);
+        t2.apply(&op2);
+
+        let op3 = t1.move_node(a, Some(b)).unwrap();
+        let op4 = t2.move_node(b, Some(a)).unwrap();
+
+        t1.apply(&op4);
+        t2.apply(&op3);
+
+        assert_eq!(t1.parent(&a), t2.parent(&a));
+        assert_eq!(t1.parent(&b), t2.parent(&b));
+        assert!(t1.parent(&a) == Some(None) || t1.parent(&b) == Some(None));
+    }
+
+    #[test]
+    fn merge_move_tree_state() {
+        let mut t1 = MoveTree::new();
+        let mut t2 = MoveTree::new();
+
+        let a = t1.create(None, "a").unwrap().child();
+        t2.merge(&t1);
+
+        let b = t2.create(Some(a), "b").unwrap().child();
+        t1.delete(a).unwrap();
+
+        t1.merge(&t2);
+        t2.merge(&t1);
+
+        assert!(!t1.contains(&a));
+        assert!(!t2.contains(&b));
+        assert!(t1.children(None).is_empty());
+        assert!(t2.children(None).is_empty());
+    }
+
+    #[test]
+    fn diff_move_tree_state() {
+        let mut t1 = MoveTree::new();
+        let mut t2 = MoveTree::new();
+
+        let a = t1.create(None, "a").unwrap().child();
+        t2.merge(&t1);
+
+        let b = t2.create(Some(a), "b").unwrap().child();
+
+        let delta = t2.diff(&t1.summary());
+
+        assert_eq!(delta.len(), 1);
+
+        t1.apply_diff(&delta);
+
+        assert!(t1.contains(&b));
+        assert_eq!(t1.children(Some(&a)), t2.children(Some(&a)));
+    }
+}